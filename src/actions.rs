@@ -0,0 +1,347 @@
+use std::collections::BTreeMap;
+use bevy::prelude::*;
+use bevy::input::InputSystem;
+use serde::{Serialize, Deserialize};
+
+use crate::config;
+
+const BINDINGS_FILE: &str = "bindings.ron";
+
+pub struct ActionsPlugin;
+
+impl Plugin for ActionsPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(Bindings::load())
+           .init_resource::<Input<Action>>()
+           .init_resource::<KeyboardCapture>()
+           .add_system_to_stage(CoreStage::PreUpdate,
+                                update_actions.after(InputSystem));
+    }
+}
+
+/*
+ * Actions
+ */
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Action {
+    Select,
+    ClearSelection,
+    Switch,
+    Mix,
+    Cycle,
+    Fourier,
+    Diffusion,
+    Measure,
+    Undo,
+    Hint,
+    ToggleLog,
+    ScrollLogUp,
+    ScrollLogDown,
+    ExportLog,
+    CycleVerbosity,
+    ToggleConsole,
+    ReplayPause,
+    ReplayBack,
+    ReplayForward,
+    ToggleWaveOverlay,
+    ToggleSound,
+    ToggleSonification,
+    CycleColorScheme,
+    ToggleReadout,
+    PanUp,
+    PanDown,
+    PanLeft,
+    PanRight,
+    ZoomIn,
+    ZoomOut,
+    FollowPlayer,
+    OpenMenu,
+    CursorUp,
+    CursorDown,
+    CursorLeft,
+    CursorRight,
+    CursorSelect,
+}
+
+impl Action {
+    // All actions, in the order they are listed in the settings menu
+    pub const ALL: [Action; 37] = [
+        Action::Select,
+        Action::ClearSelection,
+        Action::CursorUp,
+        Action::CursorDown,
+        Action::CursorLeft,
+        Action::CursorRight,
+        Action::CursorSelect,
+        Action::Switch,
+        Action::Mix,
+        Action::Cycle,
+        Action::Fourier,
+        Action::Diffusion,
+        Action::Measure,
+        Action::Undo,
+        Action::Hint,
+        Action::ToggleLog,
+        Action::ScrollLogUp,
+        Action::ScrollLogDown,
+        Action::ExportLog,
+        Action::PanUp,
+        Action::PanDown,
+        Action::PanLeft,
+        Action::PanRight,
+        Action::ZoomIn,
+        Action::ZoomOut,
+        Action::FollowPlayer,
+        Action::OpenMenu,
+        Action::CycleVerbosity,
+        Action::ToggleConsole,
+        Action::ReplayPause,
+        Action::ReplayBack,
+        Action::ReplayForward,
+        Action::ToggleWaveOverlay,
+        Action::ToggleSound,
+        Action::ToggleSonification,
+        Action::CycleColorScheme,
+        Action::ToggleReadout,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::Select => "Select",
+            Action::ClearSelection => "Clear selection",
+            Action::Switch => "Switch",
+            Action::Mix => "Mix",
+            Action::Cycle => "Cycle",
+            Action::Fourier => "Fourier",
+            Action::Diffusion => "Diffusion",
+            Action::Measure => "Measure",
+            Action::Undo => "Undo",
+            Action::Hint => "Hint",
+            Action::ToggleLog => "Show log",
+            Action::ScrollLogUp => "Scroll log up",
+            Action::ScrollLogDown => "Scroll log down",
+            Action::ExportLog => "Export log",
+            Action::CycleVerbosity => "Log verbosity",
+            Action::ToggleConsole => "Console",
+            Action::ReplayPause => "Pause replay",
+            Action::ReplayBack => "Replay step back",
+            Action::ReplayForward => "Replay step forward",
+            Action::ToggleWaveOverlay => "Phase colors",
+            Action::ToggleSound => "Sound cues",
+            Action::ToggleSonification => "Hear phases",
+            Action::CycleColorScheme => "Color scheme",
+            Action::ToggleReadout => "Numbers",
+            Action::PanUp => "Pan up",
+            Action::PanDown => "Pan down",
+            Action::PanLeft => "Pan left",
+            Action::PanRight => "Pan right",
+            Action::ZoomIn => "Zoom in",
+            Action::ZoomOut => "Zoom out",
+            Action::FollowPlayer => "Follow player",
+            Action::OpenMenu => "Menu",
+            Action::CursorUp => "Cursor up",
+            Action::CursorDown => "Cursor down",
+            Action::CursorLeft => "Cursor left",
+            Action::CursorRight => "Cursor right",
+            Action::CursorSelect => "Cursor select",
+        }
+    }
+}
+
+/*
+ * Bindings
+ */
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Binding {
+    Key(KeyCode),
+    Mouse(MouseButton),
+    // A button on any connected gamepad
+    Gamepad(GamepadButtonType),
+}
+
+impl Binding {
+    pub fn name(&self) -> String {
+        match self {
+            Binding::Key(key) => format!("{:?}", key),
+            Binding::Mouse(button) => format!("{:?} mouse", button),
+            Binding::Gamepad(button) => format!("Pad {:?}", button),
+        }
+    }
+
+    fn is_gamepad(&self) -> bool {
+        matches!(self, Binding::Gamepad(_))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Bindings {
+    pub map: BTreeMap<Action, Vec<Binding>>,
+}
+
+impl Default for Bindings {
+    fn default() -> Self {
+        use Binding::*;
+        let mut map = BTreeMap::new();
+        map.insert(Action::Select, vec![Mouse(MouseButton::Left)]);
+        map.insert(Action::ClearSelection, vec![Mouse(MouseButton::Right), Gamepad(GamepadButtonType::RightTrigger)]);
+        map.insert(Action::Switch, vec![Key(KeyCode::P), Gamepad(GamepadButtonType::West)]);
+        map.insert(Action::Mix, vec![Key(KeyCode::O), Gamepad(GamepadButtonType::North)]);
+        map.insert(Action::Cycle, vec![Key(KeyCode::K), Gamepad(GamepadButtonType::LeftThumb)]);
+        map.insert(Action::Fourier, vec![Key(KeyCode::L), Gamepad(GamepadButtonType::RightThumb)]);
+        map.insert(Action::Diffusion, vec![Key(KeyCode::J), Gamepad(GamepadButtonType::Select)]);
+        map.insert(Action::Measure, vec![Key(KeyCode::I), Gamepad(GamepadButtonType::East)]);
+        map.insert(Action::Undo, vec![Key(KeyCode::U), Gamepad(GamepadButtonType::LeftTrigger)]);
+        map.insert(Action::Hint, vec![Key(KeyCode::H)]);
+        map.insert(Action::ToggleLog, vec![Key(KeyCode::Tab)]);
+        map.insert(Action::ScrollLogUp, vec![Key(KeyCode::PageUp)]);
+        map.insert(Action::ScrollLogDown, vec![Key(KeyCode::PageDown)]);
+        map.insert(Action::ExportLog, vec![Key(KeyCode::E)]);
+        map.insert(Action::CycleVerbosity, vec![Key(KeyCode::F3)]);
+        map.insert(Action::ToggleConsole, vec![Key(KeyCode::Grave)]);
+        map.insert(Action::ReplayPause, vec![Key(KeyCode::Return)]);
+        map.insert(Action::ReplayBack, vec![Key(KeyCode::Comma)]);
+        map.insert(Action::ReplayForward, vec![Key(KeyCode::Period)]);
+        map.insert(Action::ToggleWaveOverlay, vec![Key(KeyCode::C)]);
+        map.insert(Action::ToggleSound, vec![Key(KeyCode::M)]);
+        map.insert(Action::ToggleSonification, vec![Key(KeyCode::N)]);
+        map.insert(Action::CycleColorScheme, vec![Key(KeyCode::V)]);
+        map.insert(Action::ToggleReadout, vec![Key(KeyCode::R)]);
+        map.insert(Action::PanUp, vec![Key(KeyCode::W)]);
+        map.insert(Action::PanDown, vec![Key(KeyCode::S)]);
+        map.insert(Action::PanLeft, vec![Key(KeyCode::A)]);
+        map.insert(Action::PanRight, vec![Key(KeyCode::D)]);
+        map.insert(Action::ZoomIn, vec![Key(KeyCode::X)]);
+        map.insert(Action::ZoomOut, vec![Key(KeyCode::Z)]);
+        map.insert(Action::FollowPlayer, vec![Key(KeyCode::F)]);
+        map.insert(Action::OpenMenu, vec![Key(KeyCode::Escape), Gamepad(GamepadButtonType::Start)]);
+        map.insert(Action::CursorUp, vec![Key(KeyCode::Up), Gamepad(GamepadButtonType::DPadUp)]);
+        map.insert(Action::CursorDown, vec![Key(KeyCode::Down), Gamepad(GamepadButtonType::DPadDown)]);
+        map.insert(Action::CursorLeft, vec![Key(KeyCode::Left), Gamepad(GamepadButtonType::DPadLeft)]);
+        map.insert(Action::CursorRight, vec![Key(KeyCode::Right), Gamepad(GamepadButtonType::DPadRight)]);
+        map.insert(Action::CursorSelect, vec![Key(KeyCode::Space), Gamepad(GamepadButtonType::South)]);
+        Bindings{ map }
+    }
+}
+
+impl Bindings {
+    pub fn load() -> Self {
+        /*
+         * Loads the persisted bindings. Actions missing from the
+         * file (e.g. ones added after it was saved) get their defaults.
+         */
+        let mut bindings = Bindings::default();
+        if let Some(saved) = config::load::<Bindings>(BINDINGS_FILE) {
+            bindings.map.extend(saved.map);
+        }
+        bindings
+    }
+
+    pub fn save(&self) {
+        config::save(BINDINGS_FILE, self);
+    }
+
+    pub fn get(&self, action: Action) -> &[Binding] {
+        self.map.get(&action).map(|b| b.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn rebind(&mut self, action: Action, binding: Binding) -> Vec<Action> {
+        /*
+         * Makes binding the binding of action in place of those of the
+         * same kind, keyboard and mouse or gamepad, keeping the others.
+         * Returns the other actions the binding is also bound to.
+         */
+        let bindings = self.map.entry(action).or_default();
+        bindings.retain(|bound| bound.is_gamepad() != binding.is_gamepad());
+        bindings.push(binding);
+        self.map.iter()
+            .filter(|(other, bindings)| **other != action && bindings.contains(&binding))
+            .map(|(other, _)| *other)
+            .collect()
+    }
+}
+
+// Set while text is typed, e.g. in the console,
+// so that typing does not also trigger actions
+#[derive(Default)]
+pub struct KeyboardCapture {
+    pub captured: bool,
+}
+
+/*
+ * Systems
+ */
+pub fn update_actions(
+    bindings: Res<Bindings>,
+    capture: Res<KeyboardCapture>,
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    gamepads: Res<Gamepads>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut actions: ResMut<Input<Action>>,
+    ) {
+    /*
+     * Presses and releases actions according to the bound inputs,
+     * so that systems can use Input<Action> like Input<KeyCode>
+     */
+    actions.clear();
+    for action in Action::ALL {
+        let mut pressed = false;
+        let mut just_pressed = false;
+        for binding in bindings.get(action) {
+            match binding {
+                Binding::Key(key) if !capture.captured => {
+                    pressed |= keys.pressed(*key);
+                    just_pressed |= keys.just_pressed(*key);
+                }
+                Binding::Key(_) => (),
+                Binding::Mouse(button) => {
+                    pressed |= mouse_buttons.pressed(*button);
+                    just_pressed |= mouse_buttons.just_pressed(*button);
+                }
+                Binding::Gamepad(button_type) => {
+                    for gamepad in gamepads.iter() {
+                        let button = GamepadButton(*gamepad, *button_type);
+                        pressed |= gamepad_buttons.pressed(button);
+                        just_pressed |= gamepad_buttons.just_pressed(button);
+                    }
+                }
+            }
+        }
+        // A binding can be pressed and released within one frame,
+        // in which case the action is both just pressed and just released
+        if just_pressed || (pressed && !actions.pressed(action)) {
+            actions.press(action);
+        }
+        if !pressed && actions.pressed(action) {
+            actions.release(action);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rebinding_a_key_keeps_the_gamepad_binding() {
+        let mut bindings = Bindings::default();
+        bindings.rebind(Action::Switch, Binding::Key(KeyCode::Q));
+        assert_eq!(bindings.get(Action::Switch),
+                   &[Binding::Gamepad(GamepadButtonType::West), Binding::Key(KeyCode::Q)]);
+    }
+
+    #[test]
+    fn rebinding_the_gamepad_keeps_the_key_binding() {
+        let mut bindings = Bindings::default();
+        bindings.rebind(Action::Switch, Binding::Gamepad(GamepadButtonType::South));
+        assert_eq!(bindings.get(Action::Switch),
+                   &[Binding::Key(KeyCode::P), Binding::Gamepad(GamepadButtonType::South)]);
+    }
+
+    #[test]
+    fn rebinding_reports_conflicts() {
+        let mut bindings = Bindings::default();
+        assert_eq!(bindings.rebind(Action::Switch, Binding::Key(KeyCode::O)), vec![Action::Mix]);
+        assert!(bindings.rebind(Action::Switch, Binding::Key(KeyCode::Q)).is_empty());
+    }
+}
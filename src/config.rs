@@ -0,0 +1,43 @@
+use std::fs;
+use std::path::PathBuf;
+use serde::{Serialize, de::DeserializeOwned};
+
+// Directory, relative to the working directory, that user configuration
+// is persisted in
+const CONFIG_DIR: &str = "config";
+
+pub fn config_path(file_name: &str) -> PathBuf {
+    PathBuf::from(CONFIG_DIR).join(file_name)
+}
+
+pub fn load<T: DeserializeOwned>(file_name: &str) -> Option<T> {
+    /*
+     * Reads a configuration file. Returns None if the file does not
+     * exist, and warns and returns None if it could not be parsed.
+     */
+    let path = config_path(file_name);
+    let contents = fs::read_to_string(&path).ok()?;
+    match ron::de::from_str(&contents) {
+        Ok(value) => Some(value),
+        Err(e) => {
+            bevy::log::warn!("Ignoring malformed config file {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+pub fn save<T: Serialize>(file_name: &str, value: &T) {
+    /*
+     * Writes a configuration file, creating the config directory if needed
+     */
+    let path = config_path(file_name);
+    let result = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())
+        .map_err(|e| e.to_string())
+        .and_then(|contents| {
+            fs::create_dir_all(CONFIG_DIR).map_err(|e| e.to_string())?;
+            fs::write(&path, contents).map_err(|e| e.to_string())
+        });
+    if let Err(e) = result {
+        bevy::log::warn!("Could not save config file {}: {}", path.display(), e);
+    }
+}
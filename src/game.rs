@@ -6,48 +6,141 @@ mod texture;
 mod coords;
 mod measurer;
 mod doors;
+mod picking;
+mod cursor;
+mod selection;
+mod level;
+mod palette;
+mod walls;
+mod goal;
+pub mod solver;
+mod hints;
+pub mod generator;
+mod event_log;
+mod diagnostics;
+mod console;
+mod notation;
+pub mod replay;
+mod wave_overlay;
+mod animation;
+mod collapse;
+mod sound;
+mod scheme;
+mod readout;
 
 // Imports
-use std::collections::HashMap;
 use bevy::prelude::*;
 use bevy_ecs_tilemap::prelude::*;
-use num::complex;
 use crate::AppState;
+use crate::actions::Action;
+use crate::settings::Settings;
+use crate::loading::GameAssets;
 use coords::*;
-use player::QState;
 
-#[allow(non_camel_case_types)]
-type c32 = complex::Complex32;
+pub use level::{CurrentLevel, level_names};
+pub use operations::GameRng;
 
 pub struct GamePlugin;
 
 impl Plugin for GamePlugin {
     fn build(&self, app: &mut App) {
         app.add_plugin(TilemapPlugin)
+           .add_plugin(sound::SoundPlugin)
            .add_event::<operations::SwitchEvent>()
            .add_event::<operations::MixEvent>()
+           .add_event::<operations::CycleEvent>()
+           .add_event::<operations::FourierEvent>()
+           .add_event::<operations::DiffusionEvent>()
            .add_event::<operations::MeasureEvent>()
            .add_event::<operations::MeasureSuccessEvent>()
-           .add_event::<operations::ClearSelectionEvent>()
+           .add_event::<operations::MeasureFailureEvent>()
+           .add_event::<operations::UndoEvent>()
+           .add_event::<selection::ClearSelectionEvent>()
+           .add_event::<selection::SelectTileEvent>()
+           .add_event::<console::ConsoleCommand>()
+           .init_resource::<selection::SelectionRules>()
+           .init_resource::<level::CurrentLevel>()
+           .init_resource::<level::GateInventory>()
+           .init_resource::<camera::CameraSettings>()
+           .init_resource::<coords::GridConfig>()
+           .init_resource::<event_log::LogView>()
+           .init_resource::<diagnostics::Verbosity>()
+           .init_resource::<console::Console>()
+           .init_resource::<wave_overlay::WaveOverlay>()
+           .init_resource::<scheme::Scheme>()
+           .init_resource::<readout::Readout>()
+           .init_resource::<animation::AnimationSettings>()
+           .init_resource::<operations::GameRng>()
+           .add_system_set(SystemSet::on_exit(AppState::Loading)
+                           .with_system(texture::set_texture_filters_to_nearest))
            .add_system_set(SystemSet::on_enter(AppState::InGame)
                            .with_system(setup))
             .add_system_set(SystemSet::on_update(AppState::InGame)
                             .with_system(camera::movement)
-                            .with_system(texture::set_texture_filters_to_nearest)
-                            .with_system(operations::select_positions)
+                            .with_system(camera::wheel_zoom)
+                            .with_system(camera::follow_player)
+                            .with_system(camera::snap_zoom)
+                            .with_system(selection::select_positions)
+                            .with_system(cursor::move_tile_cursor)
+                            .with_system(selection::apply_selection)
+                            .with_system(selection::show_selection)
                             .with_system(operations::switcher)
                             .with_system(operations::mixer)
+                            .with_system(operations::cycler)
+                            .with_system(operations::fourier_transformer)
+                            .with_system(operations::diffuser)
                             .with_system(operations::measure)
                             .with_system(operations::action_system)
+                            .with_system(operations::undo)
+                            .with_system(hints::give_hint)
+                            .with_system(hints::show_hint)
+                            .with_system(hints::clear_hint)
+                            .with_system(event_log::log_controls)
+                            .with_system(event_log::update_log_panel)
+                            .with_system(diagnostics::cycle_verbosity)
+                            .with_system(diagnostics::log_operations)
+                            .with_system(diagnostics::update_debug_overlay)
+                            .with_system(console::console_input)
+                            .with_system(console::run_state_commands)
+                            .with_system(console::run_gameplay_commands)
+                            .with_system(console::run_spawn_commands)
+                            .with_system(console::update_console)
+                            .with_system(replay::record_replay)
+                            .with_system(replay::play_replay)
+                            .with_system(replay::update_replay_text)
+                            .with_system(replay::save_completed_replay)
+                            .with_system(palette::update_gate_palette)
                             .with_system(doors::door_opening_system)
                             .with_system(doors::sprite_animation)
+                            .with_system(goal::goal_animation)
+                            .with_system(goal::check_goal)
+                            .with_system(animation::animate_gates)
+                            .with_system(collapse::start_collapse_effects)
+                            .with_system(collapse::animate_collapse_effects)
+                            .with_system(collapse::flash_sprites)
                             .with_system(player::update_superpositions)
                             .with_system(player::update_superposition_indicators)
-                            .with_system(operations::clear_selection)
-                            .with_system(update_transforms)) //TODO: run in posupdate stage?
+                            .with_system(wave_overlay::toggle_wave_overlay)
+                            .with_system(wave_overlay::update_wave_overlay)
+                            .with_system(scheme::cycle_scheme)
+                            .with_system(scheme::recolor_bars)
+                            .with_system(readout::toggle_readout)
+                            .with_system(readout::update_readouts)
+                            .with_system(selection::clear_selection)
+                            .with_system(update_transforms) //TODO: run in posupdate stage?
+                            .with_system(open_menu))
 
             .add_system_set(SystemSet::on_exit(AppState::InGame)
-                            .with_system(teardown));
+                            .with_system(replay::save_replay)
+                            .with_system(teardown))
+            .add_system_to_stage(CoreStage::Last, replay::save_replay_on_exit)
+            .add_system_set(SystemSet::on_enter(AppState::Reloading)
+                            .with_system(coords::apply_tile_size)
+                            .with_system(level::start_level_load))
+            .add_system_set(SystemSet::on_update(AppState::Reloading)
+                            .with_system(level::finish_level_load))
+            .add_system_set(SystemSet::on_exit(AppState::Reloading)
+                            .with_system(level::clear_loading_text));
     }
 }
 
@@ -56,36 +149,75 @@ impl Plugin for GamePlugin {
 struct MainCamera;
 
 
+#[allow(clippy::too_many_arguments)]
 fn setup(mut commands: Commands,
-         asset_server: Res<AssetServer>,
-         mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+         assets: Res<GameAssets>,
+         grid: Res<GridConfig>,
+         mut images: ResMut<Assets<Image>>,
+         level: Res<level::Level>,
+         current_level: Res<level::CurrentLevel>,
+         playback: Option<Res<replay::Playback>>,
+         scheme: Res<scheme::Scheme>,
+         settings: Res<Settings>,
+         camera_query: Query<Entity, With<MainCamera>>,
          mut map_query: MapQuery) {
-    // Spawn the camera
-    commands.spawn_bundle(OrthographicCameraBundle::new_2d())
-        .insert(MainCamera);
+    // Spawn the camera, unless it is left from a previous visit
+    if camera_query.is_empty() {
+        commands.spawn_bundle(OrthographicCameraBundle::new_2d())
+            .insert(MainCamera);
+    }
+    commands.insert_resource(operations::UndoHistory::default());
+    commands.insert_resource(selection::Selection::default());
+    commands.insert_resource(level.gates.clone());
+    commands.insert_resource(hints::HintBudget{ remaining: level.hints });
+    commands.insert_resource(event_log::OperationLog::default());
+    commands.insert_resource(wave_overlay::TintedTiles::default());
+    commands.insert_resource(animation::GateAnimation::default());
+
+    // Measurements are seeded anew for every run, unless the settings fix
+    // the seed, and the run is recorded, unless it is a replay being played back
+    match &playback {
+        Some(playback) => {
+            commands.insert_resource(operations::GameRng::new(playback.replay.seed));
+            commands.remove_resource::<replay::ReplayRecorder>();
+        }
+        None => {
+            let rng = settings.seed().map_or_else(GameRng::default, GameRng::new);
+            commands.insert_resource(replay::ReplayRecorder::new(&current_level.name, rng.seed));
+            commands.insert_resource(rng);
+        }
+    }
 
     // ====  Create the tile map =========
     // Load texture
-    let texture_handle = asset_server.load("sprites/grass_tile.png");
+    let texture_handle = assets.grass_tile.clone();
 
     // Create map entity and component:
     let map_entity = commands.spawn().id();
     let mut map = Map::new(0u16, map_entity);
 
+    // Enough chunks to cover the level
+    let chunk_size = ChunkSize(8, 8);
+    let layer_settings = LayerSettings::new(
+        MapSize((level.width + chunk_size.0 - 1) / chunk_size.0,
+                (level.height + chunk_size.1 - 1) / chunk_size.1),
+        chunk_size,
+        TileSize(coords::BASE_TILE_SIZE, coords::BASE_TILE_SIZE),
+        TextureSize(coords::BASE_TILE_SIZE, coords::BASE_TILE_SIZE),
+    );
+    // The camera is kept within the map
+    commands.insert_resource(camera::MapBounds::from_size(&grid, level.width, level.height));
+
     // Creates a new layer builder with a layer entity.
     let (mut layer_builder, _) = LayerBuilder::new(
         &mut commands,
-        LayerSettings::new(
-            MapSize(2, 2),
-            ChunkSize(8, 8),
-            TileSize(64.0, 64.0),
-            TextureSize(64.0, 64.0),
-        ),
+        layer_settings,
         0u16, // <-- Map ID
         0u16, // <-- Layer ID
     );
 
-    layer_builder.set_all(TileBundle::default());
+    layer_builder.fill(TilePos(0, 0), TilePos(level.width, level.height),
+                       TileBundle::default());
 
     // Builds the layer.
     // Note: Once this is called you can no longer edit the layer until a hard sync in bevy.
@@ -94,49 +226,85 @@ fn setup(mut commands: Commands,
     // Required to keep track of layers for a map internally.
     map.add_layer(&mut commands, 0u16, layer_entity);
 
+    // The layer tinted by phase, when the state is shown that way
+    wave_overlay::build_overlay_layer(&mut commands, &mut images, &mut map_query, &mut map,
+                                      layer_settings, level.width, level.height);
+
     // Spawn Map
     // Required in order to use map_query to retrieve layers/tiles.
+    // The layers are laid out in tiles of the art's size, and scaled to the tile size.
     commands
         .entity(map_entity)
         .insert(map)
-        .insert(Transform::from_xyz(0.0, 0.0, 0.0))
+        .insert(Transform::from_scale(Vec3::new(grid.scale(), grid.scale(), 1.)))
         .insert(GlobalTransform::default());
 
     // ====  Spawn Player ======
 
-    let mut map = HashMap::new();
-    map.insert(GridPos::new(0, 0), c32::new(1., 0.));
-    player::spawn_player(&mut commands, &asset_server, QState{map});
+    // The cursor starts on the most likely position of the player
+    let cursor_gp = level.player.map.iter()
+        .max_by(|(_, a), (_, b)| a.norm_sqr().total_cmp(&b.norm_sqr()))
+        .map(|(gp, _)| *gp)
+        .unwrap_or_else(|| GridPos::new(0, 0));
+    player::spawn_player(&mut commands, level.player.clone());
+    cursor::spawn_tile_cursor(&mut commands, &assets, &grid, cursor_gp);
+
+    // ==== Spawn walls and the goal ====
 
-    // ==== Spawn measure ====
+    walls::spawn_walls(&mut commands, &assets, &grid, &level.walls);
+    if let Some(goal_gp) = level.goal {
+        goal::spawn_goal(&mut commands, &assets, &grid, goal_gp);
+    }
+
+    // ==== Spawn measurement devices and their doors ====
 
-    let mut map = HashMap::new();
-    map.insert(GridPos::new(1, 0), c32::new(1./2_f32.sqrt(), 0.));
-    map.insert(GridPos::new(2, 0), c32::new(1./2_f32.sqrt(), 0.));
-    let m_id = measurer::spawn_measurement_device(
-        &mut commands, &asset_server, QState{map});
+    for device in level.devices.iter() {
+        let m_id = measurer::spawn_measurement_device(
+            &mut commands, &assets, &grid, *scheme, device.state.clone());
+        if let Some(door_gp) = device.door {
+            doors::spawn_door(
+                &mut commands, &assets, &grid, door_gp, m_id);
+        }
+    }
 
-    // ==== Spawn door ====
-    doors::spawn_door(
-        &mut commands, &asset_server, &mut texture_atlases, GridPos::new(2, 2), m_id);
+    // ==== Gate palette, hints and the log ====
+    palette::spawn_gate_palette(&mut commands, &assets);
+    hints::spawn_hint_text(&mut commands, &assets);
+    event_log::spawn_log_panel(&mut commands, &assets);
+    diagnostics::spawn_debug_overlay(&mut commands, &assets);
+    console::spawn_console(&mut commands, &assets);
+    if playback.is_some() {
+        replay::spawn_replay_text(&mut commands, &assets);
+    }
 
 }
 
 
 
-fn update_transforms(mut superposition_query: Query<(&GridPos, &mut Transform), Changed<GridPos>>) {
+fn update_transforms(
+    grid: Res<GridConfig>,
+    mut superposition_query: Query<(&GridPos, &mut Transform), Changed<GridPos>>,
+    ) {
     /*
      * Updates anything with a gridpos and a transform
      * whose gridpos component changed
      */
     for (gp, mut transform) in superposition_query.iter_mut() {
-        let world_pos = grid_to_world_coordinates(gp);
+        let world_pos = grid.grid_to_world(gp);
         *transform = Transform::from_xyz(world_pos.x, world_pos.y,
                                         transform.translation.z);
     }
 }
 
 
+fn open_menu(mut commands: Commands, actions: Res<Input<Action>>, mut state: ResMut<State<AppState>>) {
+    if actions.just_pressed(Action::OpenMenu) {
+        // Playing from the menu is not part of a replay
+        commands.remove_resource::<replay::Playback>();
+        state.set(AppState::MainMenu).unwrap();
+    }
+}
+
 // remove all entities that are not a camera
 fn teardown(mut commands: Commands, entities: Query<Entity, Without<Camera>>) {
     for entity in entities.iter() {
@@ -0,0 +1,216 @@
+use std::f32::consts::PI;
+use bevy::prelude::*;
+use num::complex;
+
+use super::coords::*;
+use super::event_log::{OperationLog, Outcome};
+use super::level::Gate;
+use super::player::{Player, QState};
+#[allow(non_camel_case_types)]
+type c32 = complex::Complex32;
+
+/*
+ * Components
+ */
+// The state drawn for the player. It follows the QState, but trails
+// it along the way the gate took while a gate is animated.
+#[derive(Component, Clone)]
+pub struct DisplayedState(pub QState);
+
+/*
+ * Resources
+ */
+pub struct AnimationSettings {
+    // Seconds a gate takes to play out, 0 to apply gates at once
+    pub duration: f32,
+}
+
+impl Default for AnimationSettings {
+    fn default() -> Self {
+        AnimationSettings{ duration: 0.4 }
+    }
+}
+
+// A gate raised to fractional powers, U^t, making a path from
+// a state (t = 0) to the state after the gate (t = 1)
+pub struct GatePath {
+    // The parts of the state that the gate only changes the phase of,
+    // with that phase
+    components: Vec<(f32, QState)>,
+}
+
+impl GatePath {
+    pub fn new(state: &QState, gate: Gate, tiles: &[GridPos]) -> Result<Self, String> {
+        /*
+         * A gate repeated `order` times is the identity, so its eigenvalues
+         * are order-th roots of unity λ, and the part of the state with
+         * eigenvalue λ is the mean of λ^-j U^j ψ over j. So the path needs
+         * nothing but the gate itself. Phases are taken in (-π, π],
+         * so that amplitudes take the shortest way round.
+         */
+        let order = match gate {
+            Gate::Switch | Gate::Diffusion => 2,
+            // A rotation by π/4
+            Gate::Mix => 8,
+            Gate::Cycle => tiles.len(),
+            Gate::Fourier => 4,
+            Gate::Measure => 1,
+        };
+        let mut powers = vec![state.clone()];
+        for _ in 1..order {
+            let mut next = powers[powers.len() - 1].clone();
+            next.apply(gate, tiles)?;
+            powers.push(next);
+        }
+        let components = (0..order)
+            .map(|m| {
+                let mut phase = 2. * PI * m as f32 / order as f32;
+                if phase > PI {
+                    phase -= 2. * PI;
+                }
+                let component = powers.iter()
+                    .enumerate()
+                    .map(|(j, power)| c32::from_polar(1. / order as f32, -phase * j as f32) * power.clone())
+                    .fold(QState{ map: Default::default() }, |sum, term| sum + term);
+                (phase, component)
+            })
+            .filter(|(_, component)| component.map.values().any(|factor| factor.norm_sqr() > 1e-10))
+            .collect();
+        Ok(GatePath{ components })
+    }
+
+    pub fn at(&self, t: f32) -> QState {
+        let mut state = QState{ map: Default::default() };
+        for (phase, component) in self.components.iter() {
+            for (gp, factor) in component.map.iter() {
+                let sum = state.get(gp) + factor * c32::from_polar(1., phase * t);
+                state.map.insert(*gp, sum);
+            }
+        }
+        // Drops the factors that cancel out
+        let factors: Vec<(GridPos, c32)> = state.map.drain().collect();
+        for (gp, factor) in factors {
+            state.set(gp, factor);
+        }
+        state
+    }
+}
+
+// The gate being animated, if any. Input waits for it to finish.
+#[derive(Default)]
+pub struct GateAnimation {
+    path: Option<GatePath>,
+    elapsed: f32,
+    // The QState the displayed state heads for
+    target: Option<QState>,
+    // Operations in the log when the QState last changed
+    logged: usize,
+}
+
+impl GateAnimation {
+    pub fn running(&self) -> bool {
+        self.path.is_some()
+    }
+}
+
+/*
+ * Systems
+ */
+pub fn animate_gates(
+    time: Res<Time>,
+    settings: Res<AnimationSettings>,
+    log: Res<OperationLog>,
+    mut animation: ResMut<GateAnimation>,
+    mut player_query: Query<(ChangeTrackers<QState>, &QState, &mut DisplayedState), With<Player>>,
+    ) {
+    /*
+     * Moves the displayed state along the path of the gate just applied.
+     * Anything else changing the QState, like a measurement or an undo,
+     * shows at once.
+     */
+    let (tracker, state, mut displayed) = match player_query.get_single_mut() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    if tracker.is_changed() {
+        let new_entries = log.entries.get(animation.logged..).unwrap_or(&[]);
+        animation.path = match (new_entries, &animation.target) {
+            ([entry], Some(previous)) if matches!(entry.outcome, Outcome::Applied)
+                && settings.duration > 0. =>
+                GatePath::new(previous, entry.operation.gate, &entry.operation.tiles).ok(),
+            _ => None,
+        };
+        animation.elapsed = 0.;
+        animation.logged = log.entries.len();
+        animation.target = Some(state.clone());
+        if animation.path.is_none() {
+            displayed.0 = state.clone();
+        }
+    }
+
+    animation.elapsed += time.delta_seconds();
+    let t = animation.elapsed / settings.duration;
+    if t >= 1. && animation.running() {
+        animation.path = None;
+        displayed.0 = state.clone();
+    }
+    if let Some(path) = &animation.path {
+        displayed.0 = path.at(t);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiles(n: i32) -> Vec<GridPos> {
+        (0..n).map(|x| GridPos::new(x, 0)).collect()
+    }
+
+    // A normalized state with a different magnitude and phase on each of four tiles,
+    // and some of it outside the tiles the gates act on
+    fn uneven() -> QState {
+        let mut state = QState{ map: Default::default() };
+        let factors = [c32::new(0.1, 0.5), c32::new(-0.7, 0.2), c32::new(0.3, 0.),
+                       c32::new(0., -0.4), c32::new(0.2, 0.2)];
+        for (x, factor) in factors.iter().enumerate() {
+            state.set(GridPos::new(x as i32, 0), *factor);
+        }
+        state.normalize();
+        state
+    }
+
+    fn assert_near(a: &QState, b: &QState, what: &str) {
+        for gp in a.map.keys().chain(b.map.keys()) {
+            assert!((a.get(gp) - b.get(gp)).norm() < 1e-4,
+                    "{}: differs at {}: {} vs {}", what, gp, a.get(gp), b.get(gp));
+        }
+    }
+
+    #[test]
+    fn paths_go_from_the_state_to_the_gate_applied() {
+        let gates = [(Gate::Switch, 2), (Gate::Mix, 2), (Gate::Diffusion, 2), (Gate::Diffusion, 4),
+                     (Gate::Cycle, 2), (Gate::Cycle, 3), (Gate::Cycle, 4),
+                     (Gate::Fourier, 2), (Gate::Fourier, 3), (Gate::Fourier, 4)];
+        for (gate, k) in gates {
+            let what = format!("{} on {} tiles", gate.name(), k);
+            let start = uneven();
+            let path = GatePath::new(&start, gate, &tiles(k)).unwrap();
+            let mut end = start.clone();
+            end.apply(gate, &tiles(k)).unwrap();
+
+            assert_near(&path.at(0.), &start, &what);
+            assert_near(&path.at(1.), &end, &what);
+            for step in 1..10 {
+                let norm_sqr: f32 = path.at(step as f32 / 10.).map.values()
+                    .map(|factor| factor.norm_sqr()).sum();
+                assert!((norm_sqr - 1.).abs() < 1e-4, "{}: norm {} at t = {}", what, norm_sqr, step);
+            }
+        }
+    }
+
+    #[test]
+    fn no_path_for_the_wrong_tiles() {
+        assert!(GatePath::new(&uneven(), Gate::Mix, &tiles(3)).is_err());
+    }
+}
@@ -1,46 +1,319 @@
-use bevy::{core::Time, input::Input, math::Vec3, prelude::*, render::camera::Camera};
+use bevy::{core::Time, input::Input, math::Vec3, prelude::*};
+use bevy::input::mouse::{MouseScrollUnit, MouseWheel};
+use bevy::render::camera::Camera;
 
+use crate::actions::Action;
+use super::MainCamera;
+use super::coords::*;
+use super::picking::cursor_world_position;
+use super::player::{Player, QState};
+
+/*
+ * Resources
+ */
+pub struct CameraSettings {
+    // Whether the camera follows the centroid of the player superposition
+    pub follow_player: bool,
+    // How quickly the camera catches up with the player, per second
+    pub follow_speed: f32,
+    // Limits of the orthographic scale (smaller is zoomed in)
+    pub min_zoom: f32,
+    pub max_zoom: f32,
+    // Factor the scale changes with per line scrolled
+    pub wheel_zoom_step: f32,
+    // Whether the zoom settles on scales that draw every texture pixel
+    // as a whole number of screen pixels, keeping pixel art crisp
+    pub snap_zoom: bool,
+}
+
+impl Default for CameraSettings {
+    fn default() -> Self {
+        CameraSettings {
+            follow_player: false,
+            follow_speed: 4.,
+            min_zoom: 0.25,
+            max_zoom: 4.,
+            wheel_zoom_step: 1.1,
+            snap_zoom: true,
+        }
+    }
+}
+
+// Most screen pixels per texture pixel, and the other way around,
+// that the zoom snaps to
+const MAX_PIXEL_RATIO: u32 = 8;
+
+fn zoom_levels(scale_factor: f32, grid: &GridConfig, min_zoom: f32, max_zoom: f32) -> Vec<f32> {
+    /*
+     * The orthographic scales within the limits at which a texture pixel
+     * covers n x n screen pixels, or n x n texture pixels share one.
+     * A world unit covers scale_factor / scale screen pixels,
+     * and a texture pixel grid.scale() world units.
+     */
+    let pixel = scale_factor * grid.scale();
+    let mut levels: Vec<f32> = (1..=MAX_PIXEL_RATIO)
+        .flat_map(|n| [pixel / n as f32, pixel * n as f32])
+        .filter(|scale| (min_zoom..=max_zoom).contains(scale))
+        .collect();
+    levels.sort_by(f32::total_cmp);
+    levels.dedup();
+    levels
+}
+
+fn nearest_level(levels: &[f32], scale: f32) -> Option<usize> {
+    // Nearest by ratio rather than difference, as zooming is multiplicative
+    (0..levels.len()).min_by(|&a, &b| {
+        let distance = |i: usize| (levels[i] / scale).ln().abs();
+        distance(a).total_cmp(&distance(b))
+    })
+}
+
+pub struct MapBounds {
+    pub min: Vec2,
+    pub max: Vec2,
+}
+
+impl MapBounds {
+    pub fn from_size(grid: &GridConfig, width: u32, height: u32) -> Self {
+        /*
+         * The bounds of a map of width x height tiles
+         * whose bottom left tile is at the origin
+         */
+        let corner = |x, y| grid.grid_to_world(&GridPos::new(x, y));
+        MapBounds {
+            min: Vec2::ZERO,
+            max: corner(width as i32, height as i32) - corner(0, 0),
+        }
+    }
+}
+
+/*
+ * Systems
+ */
 // A simple camera system for moving and zooming the camera.
 pub fn movement(
     time: Res<Time>,
-    keyboard_input: Res<Input<KeyCode>>,
-    mut query: Query<(&mut Transform, &mut OrthographicProjection), With<Camera>>,
+    actions: Res<Input<Action>>,
+    mut settings: ResMut<CameraSettings>,
+    bounds: Option<Res<MapBounds>>,
+    mut query: Query<(&mut Transform, &mut OrthographicProjection), With<MainCamera>>,
 ) {
+    if actions.just_pressed(Action::FollowPlayer) {
+        settings.follow_player = !settings.follow_player;
+    }
+
     for (mut transform, mut ortho) in query.iter_mut() {
         let mut direction = Vec3::ZERO;
 
-        if keyboard_input.pressed(KeyCode::A) {
+        if actions.pressed(Action::PanLeft) {
             direction -= ortho.scale*Vec3::new(1.0, 0.0, 0.0);
         }
 
-        if keyboard_input.pressed(KeyCode::D) {
+        if actions.pressed(Action::PanRight) {
             direction += ortho.scale*Vec3::new(1.0, 0.0, 0.0);
         }
 
-        if keyboard_input.pressed(KeyCode::W) {
+        if actions.pressed(Action::PanUp) {
             direction += ortho.scale*Vec3::new(0.0, 1.0, 0.0);
         }
 
-        if keyboard_input.pressed(KeyCode::S) {
+        if actions.pressed(Action::PanDown) {
             direction -= ortho.scale*Vec3::new(0.0, 1.0, 0.0);
         }
 
-        if keyboard_input.pressed(KeyCode::Z) {
+        if actions.pressed(Action::ZoomOut) {
             ortho.scale *= 1.03;
         }
 
-        if keyboard_input.pressed(KeyCode::X) {
+        if actions.pressed(Action::ZoomIn) {
             ortho.scale /= 1.03;
         }
 
-        //if ortho.scale < 0.5 {
-            //ortho.scale = 0.5;
-        //}
+        ortho.scale = ortho.scale.clamp(settings.min_zoom, settings.max_zoom);
+
+        // Panning by hand takes over from following the player
+        if direction != Vec3::ZERO {
+            settings.follow_player = false;
+        }
 
         let z = transform.translation.z;
         transform.translation += time.delta_seconds() * direction * 500.;
         // Important! We need to restore the Z values when moving the camera around.
         // Bevy has a specific camera setup and this can mess with how our layers are shown.
         transform.translation.z = z;
+
+        if let Some(bounds) = &bounds {
+            clamp_to_bounds(&mut transform, &ortho, bounds);
+        }
+    }
+}
+
+pub fn wheel_zoom(
+    mut wheel_events: EventReader<MouseWheel>,
+    windows: Res<Windows>,
+    grid: Res<GridConfig>,
+    settings: Res<CameraSettings>,
+    bounds: Option<Res<MapBounds>>,
+    cursor_camera_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    mut query: Query<(&mut Transform, &mut OrthographicProjection), With<MainCamera>>,
+) {
+    /*
+     * Zooms with the mouse wheel, keeping the point under the cursor fixed
+     */
+    let mut lines = 0.;
+    for event in wheel_events.iter() {
+        lines += match event.unit {
+            MouseScrollUnit::Line => event.y,
+            // Roughly what one line corresponds to on most touchpads
+            MouseScrollUnit::Pixel => event.y / 16.,
+        };
+    }
+    if lines == 0. {
+        return;
+    }
+
+    // The point that should stay under the cursor.
+    // Falls back to zooming about the center of the view.
+    let anchor = cursor_world_position(&windows, &cursor_camera_query);
+
+    let levels = match windows.get_primary().filter(|_| settings.snap_zoom) {
+        Some(window) => zoom_levels(window.scale_factor() as f32, &grid, settings.min_zoom, settings.max_zoom),
+        None => Vec::new(),
+    };
+
+    for (mut transform, mut ortho) in query.iter_mut() {
+        let old_scale = ortho.scale;
+        ortho.scale = match nearest_level(&levels, old_scale) {
+            // Step from level to level, a line at a time and
+            // at least one level however little was scrolled
+            Some(i) => {
+                let steps = if lines.abs() < 1. { lines.signum() } else { lines.round() };
+                let next = (i as isize - steps as isize).clamp(0, levels.len() as isize - 1);
+                levels[next as usize]
+            }
+            None => (old_scale * settings.wheel_zoom_step.powf(-lines))
+                .clamp(settings.min_zoom, settings.max_zoom),
+        };
+
+        if let Some(anchor) = anchor {
+            // Scaling the view about the anchor moves the camera
+            // along the line between them
+            let ratio = ortho.scale / old_scale;
+            let camera_pos = transform.translation.truncate();
+            let new_pos = anchor + (camera_pos - anchor) * ratio;
+            transform.translation.x = new_pos.x;
+            transform.translation.y = new_pos.y;
+        }
+
+        if let Some(bounds) = &bounds {
+            clamp_to_bounds(&mut transform, &ortho, bounds);
+        }
+    }
+}
+
+pub fn snap_zoom(
+    actions: Res<Input<Action>>,
+    windows: Res<Windows>,
+    grid: Res<GridConfig>,
+    settings: Res<CameraSettings>,
+    bounds: Option<Res<MapBounds>>,
+    mut query: Query<(&mut Transform, &mut OrthographicProjection), With<MainCamera>>,
+) {
+    /*
+     * Settles the zoom on the nearest whole pixel ratio once the zoom
+     * keys are let go, so that sprites are not drawn with uneven pixels
+     */
+    if !settings.snap_zoom
+        || actions.pressed(Action::ZoomIn)
+        || actions.pressed(Action::ZoomOut) {
+        return;
+    }
+    let Some(window) = windows.get_primary() else { return };
+    let levels = zoom_levels(window.scale_factor() as f32, &grid, settings.min_zoom, settings.max_zoom);
+
+    for (mut transform, mut ortho) in query.iter_mut() {
+        let scale = match nearest_level(&levels, ortho.scale) {
+            Some(i) => levels[i],
+            None => continue,
+        };
+        if scale == ortho.scale {
+            continue;
+        }
+        ortho.scale = scale;
+        if let Some(bounds) = &bounds {
+            clamp_to_bounds(&mut transform, &ortho, bounds);
+        }
+    }
+}
+
+pub fn follow_player(
+    time: Res<Time>,
+    grid: Res<GridConfig>,
+    settings: Res<CameraSettings>,
+    bounds: Option<Res<MapBounds>>,
+    player_query: Query<&QState, With<Player>>,
+    mut query: Query<(&mut Transform, &OrthographicProjection), With<MainCamera>>,
+) {
+    /*
+     * Moves the camera towards the probability weighted
+     * centroid of the player superposition
+     */
+    if !settings.follow_player {
+        return;
+    }
+    let target = if let Ok(state) = player_query.get_single() {
+        if let Some(target) = centroid(&grid, state) {
+            target
+        } else {return;}
+    } else {return;};
+
+    // Exponential approach, independent of the frame rate
+    let t = 1. - (-settings.follow_speed * time.delta_seconds()).exp();
+    for (mut transform, ortho) in query.iter_mut() {
+        let camera_pos = transform.translation.truncate();
+        let new_pos = camera_pos.lerp(target, t);
+        transform.translation.x = new_pos.x;
+        transform.translation.y = new_pos.y;
+
+        if let Some(bounds) = &bounds {
+            clamp_to_bounds(&mut transform, ortho, bounds);
+        }
+    }
+}
+
+fn centroid(grid: &GridConfig, state: &QState) -> Option<Vec2> {
+    /*
+     * Probability weighted mean of the world positions in the state
+     */
+    let mut total = 0.;
+    let mut sum = Vec2::ZERO;
+    for (gp, factor) in state.map.iter() {
+        let prob = factor.norm_sqr();
+        total += prob;
+        sum += prob * grid.grid_to_world(gp);
+    }
+    if total > 0. { Some(sum / total) } else { None }
+}
+
+fn clamp_to_bounds(transform: &mut Transform, ortho: &OrthographicProjection, bounds: &MapBounds) {
+    /*
+     * Keeps the view inside the map. If the map is smaller than
+     * the view along some axis the map is centered along that axis instead.
+     */
+    let half_view = Vec2::new(ortho.right - ortho.left, ortho.top - ortho.bottom)
+        * ortho.scale / 2.;
+    let center = (bounds.min + bounds.max) / 2.;
+    let lower = bounds.min + half_view;
+    let upper = bounds.max - half_view;
+
+    if lower.x <= upper.x {
+        transform.translation.x = transform.translation.x.clamp(lower.x, upper.x);
+    } else {
+        transform.translation.x = center.x;
+    }
+    if lower.y <= upper.y {
+        transform.translation.y = transform.translation.y.clamp(lower.y, upper.y);
+    } else {
+        transform.translation.y = center.y;
     }
 }
@@ -0,0 +1,223 @@
+use std::f32::consts::PI;
+use bevy::prelude::*;
+
+use crate::loading::GameAssets;
+use super::coords::*;
+use super::doors::OpenableByMeasurement;
+use super::measurer::{DeviceArrow, MeasurementDevice, MeasurementIndicator};
+use super::operations::{MeasureFailureEvent, MeasureSuccessEvent};
+use super::player::{Player, QState};
+
+// Seconds the vanishing branches take to burst and the surviving ones to pulse
+const COLLAPSE_SECONDS: f32 = 0.5;
+// Seconds the signal takes from the device to its door
+const SIGNAL_SECONDS: f32 = 0.4;
+// Seconds a flash lasts, and how often it blinks meanwhile
+const FLASH_SECONDS: f32 = 0.6;
+const FLASH_BLINKS: f32 = 3.;
+// Drawn above the player and the devices, below the selection
+const EFFECT_Z: f32 = 15.;
+
+/*
+ * Components
+ */
+#[derive(Component)]
+pub struct CollapseEffect {
+    timer: Timer,
+    kind: EffectKind,
+}
+
+enum EffectKind {
+    // A branch the measurement ruled out, growing as it fades away
+    Burst,
+    // A branch that is left, swelling once
+    Pulse,
+    // A spark on its way from a device to the door it opens
+    Signal{ from: Vec2, to: Vec2, door: Entity },
+}
+
+// A sprite blinking in a color for a while, after which it is white again
+#[derive(Component)]
+pub struct Flash {
+    timer: Timer,
+    color: Color,
+}
+
+impl Flash {
+    fn new(color: Color) -> Self {
+        Flash{ timer: Timer::from_seconds(FLASH_SECONDS, false), color }
+    }
+
+    fn color(&self) -> Color {
+        if self.timer.finished() {
+            return Color::WHITE;
+        }
+        let blink = (self.timer.percent() * FLASH_BLINKS * 2.) as u32;
+        if blink % 2 == 0 { self.color } else { Color::WHITE }
+    }
+}
+
+fn spawn_effect(
+    commands: &mut Commands,
+    texture: Handle<Image>,
+    size: Vec2,
+    color: Color,
+    world_pos: Vec2,
+    seconds: f32,
+    kind: EffectKind,
+    ) {
+    commands.spawn_bundle(SpriteBundle {
+        texture,
+        transform: Transform::from_xyz(world_pos.x, world_pos.y, EFFECT_Z),
+        sprite: Sprite{ color, custom_size: Some(size), ..Default::default() },
+        ..Default::default()
+    })
+    .insert(CollapseEffect{ timer: Timer::from_seconds(seconds, false), kind });
+}
+
+/*
+ * Systems
+ */
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn start_collapse_effects(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    grid: Res<GridConfig>,
+    mut previous: Local<Option<QState>>,
+    mut success_reader: EventReader<MeasureSuccessEvent>,
+    mut failure_reader: EventReader<MeasureFailureEvent>,
+    player_query: Query<(ChangeTrackers<QState>, &QState), With<Player>>,
+    device_query: Query<(&QState, &Children), (With<MeasurementDevice>, Without<Player>)>,
+    door_query: Query<(Entity, &GridPos, &OpenableByMeasurement)>,
+    indicator_query: Query<&Children, With<MeasurementIndicator>>,
+    arrow_query: Query<Entity, With<DeviceArrow>>,
+    ) {
+    /*
+     * Plays out the measurements of the frame. The state is remembered
+     * whenever it changes, so that the branches a measurement made vanish
+     * can burst where they were while the branches left pulse. A success
+     * sends a signal from the device to its doors, a failure flashes
+     * the arrows of the device.
+     */
+    let (tracker, state) = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let successes: Vec<Entity> = success_reader.iter().map(|event| event.entity).collect();
+    let failures: Vec<Entity> = failure_reader.iter().map(|event| event.entity).collect();
+
+    if !successes.is_empty() || !failures.is_empty() {
+        if let Some(previous) = previous.as_ref() {
+            for gp in previous.map.keys().filter(|gp| !state.map.contains_key(gp)) {
+                spawn_effect(&mut commands, assets.player.clone(), grid.tile(),
+                             Color::rgba(1., 1., 1., 0.8), grid.grid_to_world(gp),
+                             COLLAPSE_SECONDS, EffectKind::Burst);
+            }
+        }
+        for gp in state.map.keys() {
+            spawn_effect(&mut commands, assets.player.clone(), grid.tile(),
+                         Color::rgba(1., 0.95, 0.6, 0.6), grid.grid_to_world(gp),
+                         COLLAPSE_SECONDS, EffectKind::Pulse);
+        }
+    }
+
+    for device in successes {
+        let tiles = match device_query.get(device) {
+            Ok((device_state, _)) => device_state.map.keys()
+                .map(|gp| grid.grid_to_world(gp))
+                .collect::<Vec<Vec2>>(),
+            Err(_) => continue,
+        };
+        if tiles.is_empty() {
+            continue;
+        }
+        let from = tiles.iter().fold(Vec2::ZERO, |sum, pos| sum + *pos) / tiles.len() as f32;
+        for (door, gp, openable) in door_query.iter() {
+            if openable.measurement_device_entity == device {
+                spawn_effect(&mut commands, assets.select.clone(), grid.tile(),
+                             Color::rgb(0.2, 0.87, 0.08), from, SIGNAL_SECONDS,
+                             EffectKind::Signal{ from, to: grid.grid_to_world(gp), door });
+            }
+        }
+    }
+    for device in failures {
+        let indicators = match device_query.get(device) {
+            Ok((_, indicators)) => indicators,
+            Err(_) => continue,
+        };
+        for children in indicators.iter().filter_map(|indicator| indicator_query.get(*indicator).ok()) {
+            for arrow in children.iter().filter(|child| arrow_query.get(**child).is_ok()) {
+                commands.entity(*arrow).insert(Flash::new(Color::rgb(1., 0.2, 0.2)));
+            }
+        }
+    }
+
+    if tracker.is_changed() {
+        *previous = Some(state.clone());
+    }
+}
+
+pub fn animate_collapse_effects(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut effect_query: Query<(Entity, &mut CollapseEffect, &mut Transform, &mut Sprite)>,
+    door_query: Query<(), With<OpenableByMeasurement>>,
+    ) {
+    /*
+     * Grows, fades and moves the effect sprites, despawning them once
+     * they are done. A signal reaching its door makes the door flash.
+     */
+    for (entity, mut effect, mut transform, mut sprite) in effect_query.iter_mut() {
+        effect.timer.tick(time.delta());
+        let t = effect.timer.percent();
+        match effect.kind {
+            EffectKind::Burst => {
+                transform.scale = Vec3::splat(1. + t);
+                sprite.color.set_a(0.8 * (1. - t));
+            }
+            EffectKind::Pulse => {
+                transform.scale = Vec3::splat(1. + 0.3 * (PI * t).sin());
+                sprite.color.set_a(0.6 * (1. - t));
+            }
+            EffectKind::Signal{ from, to, .. } => {
+                let pos = from.lerp(to, t);
+                transform.translation.x = pos.x;
+                transform.translation.y = pos.y;
+                transform.scale = Vec3::splat(0.5);
+            }
+        }
+        if effect.timer.finished() {
+            if let EffectKind::Signal{ door, .. } = effect.kind {
+                if door_query.get(door).is_ok() {
+                    commands.entity(door).insert(Flash::new(Color::rgb(0.4, 1., 0.4)));
+                }
+            }
+            commands.entity(entity).despawn();
+        }
+    }
+}
+
+pub fn flash_sprites(
+    mut commands: Commands,
+    time: Res<Time>,
+    mut sprite_query: Query<(Entity, &mut Flash, &mut Sprite)>,
+    mut atlas_query: Query<(Entity, &mut Flash, &mut TextureAtlasSprite), Without<Sprite>>,
+    ) {
+    /*
+     * Blinks the flashing sprites, plain or from an atlas like the doors
+     */
+    for (entity, mut flash, mut sprite) in sprite_query.iter_mut() {
+        flash.timer.tick(time.delta());
+        sprite.color = flash.color();
+        if flash.timer.finished() {
+            commands.entity(entity).remove::<Flash>();
+        }
+    }
+    for (entity, mut flash, mut sprite) in atlas_query.iter_mut() {
+        flash.timer.tick(time.delta());
+        sprite.color = flash.color();
+        if flash.timer.finished() {
+            commands.entity(entity).remove::<Flash>();
+        }
+    }
+}
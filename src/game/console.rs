@@ -0,0 +1,465 @@
+use bevy::prelude::*;
+use bevy::window::ReceivedCharacter;
+use num::complex;
+
+use crate::AppState;
+use crate::actions::{Action, Binding, Bindings, KeyboardCapture};
+use crate::loading::GameAssets;
+use super::animation::AnimationSettings;
+use super::coords::*;
+use super::doors::{self, OpenableByMeasurement};
+use super::level::{CurrentLevel, DeviceSpec, Gate, Level};
+use super::measurer::{self, MeasurementDevice};
+use super::operations::*;
+use super::player::{Player, QState};
+use super::scheme::Scheme;
+#[allow(non_camel_case_types)]
+type c32 = complex::Complex32;
+
+// Lines of output kept and shown above the input line
+const VISIBLE_LINES: usize = 10;
+
+const HELP: &str = "\
+state [<state>]: show or set the player's state, normalized, e.g. 1/sqrt2 (|1,0> + i|2,0>)
+set <x> <y> <re> <im>: set the player's factor at a tile, then normalize
+normalize: scale the player's state to probability 1
+switch|mix|cycle|fourier|diffusion <x> <y> <x> <y> ...: apply a gate
+devices: list the measurement devices
+measure <entity>: measure with a device
+spawn device <state> [door <x> <y>]: add a device
+open door [<x> <y>]: open a door, the first closed one by default
+seed [<n>]: show or set the seed of the measurement outcomes
+animation <seconds>: set how long gates take to play out, 0 for at once
+load level <name>: load a level or level code";
+
+/*
+ * Components
+ */
+#[derive(Component)]
+pub struct ConsolePanel;
+#[derive(Component)]
+pub struct ConsoleText;
+
+/*
+ * Events
+ */
+// A parsed console command, run by the system responsible for it
+pub enum ConsoleCommand {
+    Help,
+    State(Option<QState>),
+    Set(GridPos, c32),
+    Normalize,
+    Gate(Gate, Vec<GridPos>),
+    Devices,
+    Measure(u32),
+    SpawnDevice(DeviceSpec),
+    OpenDoor(Option<GridPos>),
+    Seed(Option<u64>),
+    Animation(f32),
+    LoadLevel(String),
+}
+
+impl ConsoleCommand {
+    pub fn parse(line: &str) -> Result<Self, String> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let command = match words[..] {
+            ["help"] => ConsoleCommand::Help,
+            ["state"] => ConsoleCommand::State(None),
+            ["state", ..] => ConsoleCommand::State(Some(
+                line.trim_start().trim_start_matches("state").parse()?)),
+            ["set", x, y, re, im] => ConsoleCommand::Set(
+                parse_pos(x, y)?, c32::new(parse_number(re)?, parse_number(im)?)),
+            ["normalize"] => ConsoleCommand::Normalize,
+            [gate @ ("switch" | "mix" | "cycle" | "fourier" | "diffusion"), ..] => {
+                let gate = match gate {
+                    "switch" => Gate::Switch,
+                    "mix" => Gate::Mix,
+                    "cycle" => Gate::Cycle,
+                    "fourier" => Gate::Fourier,
+                    _ => Gate::Diffusion,
+                };
+                let tiles = parse_positions(&words[1..])?;
+                gate.check_tiles(&tiles)?;
+                ConsoleCommand::Gate(gate, tiles)
+            }
+            ["devices"] => ConsoleCommand::Devices,
+            ["measure", entity] => ConsoleCommand::Measure(
+                entity.split('v').next().unwrap_or(entity).parse()
+                    .map_err(|_| format!("{} is not an entity", entity))?),
+            ["spawn", "device", ..] => {
+                let (state, door) = match words.iter().position(|word| *word == "door") {
+                    Some(i) => match words[i + 1..] {
+                        [x, y] => (&words[2..i], Some(parse_pos(x, y)?)),
+                        _ => return Err("door takes <x> <y>".to_string()),
+                    },
+                    None => (&words[2..], None),
+                };
+                let state: QState = state.join(" ").parse()?;
+                if state.map.is_empty() {
+                    return Err("a device needs a state".to_string());
+                }
+                ConsoleCommand::SpawnDevice(DeviceSpec{ state, door })
+            }
+            ["open", "door"] => ConsoleCommand::OpenDoor(None),
+            ["open", "door", x, y] => ConsoleCommand::OpenDoor(Some(parse_pos(x, y)?)),
+            ["seed"] => ConsoleCommand::Seed(None),
+            ["seed", seed] => ConsoleCommand::Seed(Some(
+                seed.parse().map_err(|_| format!("{} is not a seed", seed))?)),
+            ["animation", seconds] => ConsoleCommand::Animation(parse_number(seconds)?.max(0.)),
+            ["load", "level", name] => ConsoleCommand::LoadLevel(name.to_string()),
+            [] => return Err(String::new()),
+            _ => return Err(format!("Unknown command: {} (try help)", line.trim())),
+        };
+        Ok(command)
+    }
+}
+
+fn parse_number(word: &str) -> Result<f32, String> {
+    word.parse().map_err(|_| format!("{} is not a number", word))
+}
+
+fn parse_pos(x: &str, y: &str) -> Result<GridPos, String> {
+    let coordinate = |word: &str| word.parse::<i32>()
+        .map_err(|_| format!("{} is not a coordinate", word));
+    Ok(GridPos::new(coordinate(x)?, coordinate(y)?))
+}
+
+fn parse_positions(words: &[&str]) -> Result<Vec<GridPos>, String> {
+    if words.len() % 2 != 0 {
+        return Err("tiles are given as <x> <y> pairs".to_string());
+    }
+    let mut tiles: Vec<GridPos> = Vec::new();
+    for pair in words.chunks(2) {
+        let gp = parse_pos(pair[0], pair[1])?;
+        if tiles.contains(&gp) {
+            return Err(format!("{} is given twice", gp));
+        }
+        tiles.push(gp);
+    }
+    Ok(tiles)
+}
+
+/*
+ * Resources
+ */
+#[derive(Default)]
+pub struct Console {
+    open: bool,
+    input: String,
+    // Commands entered and what came of them, oldest first
+    lines: Vec<String>,
+}
+
+impl Console {
+    pub fn print(&mut self, message: impl Into<String>) {
+        let message = message.into();
+        self.lines.extend(message.lines().map(str::to_string));
+        let excess = self.lines.len().saturating_sub(VISIBLE_LINES);
+        self.lines.drain(..excess);
+    }
+}
+
+pub fn spawn_console(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    ) {
+    /*
+     * Spawns the console across the top of the screen, hidden.
+     * Its text is filled in by update_console.
+     */
+    commands.spawn_bundle(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                left: Val::Px(0.0),
+                top: Val::Px(0.0),
+                ..Default::default()
+            },
+            size: Size::new(Val::Percent(100.0), Val::Auto),
+            padding: Rect::all(Val::Px(8.0)),
+            ..Default::default()
+        },
+        color: Color::rgba(0., 0., 0., 0.85).into(),
+        visibility: Visibility{ is_visible: false },
+        ..Default::default()
+    })
+    .insert(ConsolePanel)
+    .with_children(|parent| {
+        parent.spawn_bundle(TextBundle {
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: assets.font.clone(),
+                    font_size: 18.0,
+                    color: Color::rgb(0.6, 1.0, 0.6),
+                },
+                Default::default(),
+            ),
+            visibility: Visibility{ is_visible: false },
+            ..Default::default()
+        })
+        .insert(ConsoleText);
+    });
+}
+
+/*
+ * Systems
+ */
+pub fn console_input(
+    actions: Res<Input<Action>>,
+    keys: Res<Input<KeyCode>>,
+    bindings: Res<Bindings>,
+    mut characters: EventReader<ReceivedCharacter>,
+    mut console: ResMut<Console>,
+    mut capture: ResMut<KeyboardCapture>,
+    mut command_writer: EventWriter<ConsoleCommand>,
+    ) {
+    /*
+     * Opens and closes the console and edits its input line.
+     * Enter parses the line and sends the command.
+     */
+    if !console.open {
+        // The keyboard is given back only once all keys are up,
+        // so that held keys do not trigger actions on release
+        if capture.captured && keys.get_pressed().next().is_none() {
+            capture.captured = false;
+        }
+        if actions.just_pressed(Action::ToggleConsole) {
+            console.open = true;
+            capture.captured = true;
+            // Drop the character of the key that opened the console
+            characters.iter().for_each(drop);
+        }
+        return;
+    }
+
+    let toggle_pressed = bindings.map.get(&Action::ToggleConsole)
+        .into_iter()
+        .flatten()
+        .any(|binding| matches!(binding, Binding::Key(key) if keys.just_pressed(*key)));
+    if toggle_pressed || keys.just_pressed(KeyCode::Escape) {
+        console.open = false;
+        characters.iter().for_each(drop);
+        return;
+    }
+
+    for character in characters.iter() {
+        if !character.char.is_control() {
+            console.input.push(character.char);
+        }
+    }
+    if keys.just_pressed(KeyCode::Back) {
+        console.input.pop();
+    }
+    if keys.just_pressed(KeyCode::Return) {
+        let line = std::mem::take(&mut console.input);
+        console.print(format!("> {}", line));
+        match ConsoleCommand::parse(&line) {
+            Ok(command) => command_writer.send(command),
+            Err(e) if e.is_empty() => (),
+            Err(e) => console.print(e),
+        }
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn run_state_commands(
+    mut command_reader: EventReader<ConsoleCommand>,
+    mut console: ResMut<Console>,
+    mut player_query: Query<&mut QState, With<Player>>,
+    device_query: Query<(Entity, &QState), (With<MeasurementDevice>, Without<Player>)>,
+    mut rng: ResMut<GameRng>,
+    mut animation: ResMut<AnimationSettings>,
+    mut current_level: ResMut<CurrentLevel>,
+    mut state: ResMut<State<AppState>>,
+    ) {
+    /*
+     * Runs the commands that have no gameplay event,
+     * acting on the state and resources directly
+     */
+    for command in command_reader.iter() {
+        match command {
+            ConsoleCommand::Help => console.print(HELP),
+            ConsoleCommand::State(None) => {
+                for player_state in player_query.iter() {
+                    console.print(player_state.to_string());
+                }
+            }
+            // States are normalized, as measurements take the probabilities to add up to 1
+            ConsoleCommand::State(Some(new_state)) => {
+                let mut new_state = new_state.clone();
+                new_state.normalize();
+                if new_state.map.is_empty() {
+                    console.print("The player's state can not be 0");
+                    continue;
+                }
+                for mut player_state in player_query.iter_mut() {
+                    *player_state = new_state.clone();
+                }
+                console.print(format!("State set to {}", new_state));
+            }
+            ConsoleCommand::Set(gp, factor) => {
+                for mut player_state in player_query.iter_mut() {
+                    let mut new_state = player_state.clone();
+                    new_state.set(*gp, *factor);
+                    new_state.normalize();
+                    if new_state.map.is_empty() {
+                        console.print("The player's state can not be 0");
+                        continue;
+                    }
+                    *player_state = new_state;
+                    console.print(format!("Set {} to {:.3}, then normalized to {}",
+                                          gp, factor, *player_state));
+                }
+            }
+            ConsoleCommand::Normalize => {
+                for mut player_state in player_query.iter_mut() {
+                    player_state.normalize();
+                }
+                console.print("Normalized");
+            }
+            ConsoleCommand::Devices => {
+                for (entity, device_state) in device_query.iter() {
+                    console.print(format!("{}: {}", entity.id(), device_state));
+                }
+                if device_query.is_empty() {
+                    console.print("No measurement devices");
+                }
+            }
+            ConsoleCommand::Seed(None) => console.print(format!("Seed {}", rng.seed)),
+            ConsoleCommand::Seed(Some(seed)) => {
+                *rng = GameRng::new(*seed);
+                console.print(format!("Measurements seeded with {}", seed));
+            }
+            ConsoleCommand::Animation(seconds) => {
+                animation.duration = *seconds;
+                console.print(format!("Gates take {} s", seconds));
+            }
+            ConsoleCommand::LoadLevel(name) => {
+                current_level.name = name.clone();
+                console.open = false;
+                // The game is set up again when it is entered anew
+                match state.set(AppState::Reloading) {
+                    Ok(()) => console.print(format!("Loading {}", name)),
+                    Err(e) => console.print(format!("Could not load {}: {:?}", name, e)),
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+pub fn run_gameplay_commands(
+    mut commands: Commands,
+    mut command_reader: EventReader<ConsoleCommand>,
+    mut console: ResMut<Console>,
+    device_query: Query<Entity, With<MeasurementDevice>>,
+    door_query: Query<(Entity, &GridPos, Option<&Blocking>), With<OpenableByMeasurement>>,
+    mut operation_writers: OperationWriters,
+    ) {
+    /*
+     * Runs the commands that gameplay has events for by sending
+     * those events, so they play out exactly as in the game.
+     * The gates' uses are not counted. Doors are opened directly,
+     * as nothing is measured, so no success is played out.
+     */
+    for command in command_reader.iter() {
+        match command {
+            ConsoleCommand::Gate(gate, tiles) => {
+                match operation_writers.send_gate(*gate, tiles.clone()) {
+                    Ok(()) => console.print(format!("Applied {}", gate.name())),
+                    Err(e) => console.print(e),
+                }
+            }
+            ConsoleCommand::Measure(id) => {
+                match device_query.iter().find(|entity| entity.id() == *id) {
+                    Some(entity) => {
+                        operation_writers.measure.send(MeasureEvent{ entity });
+                        console.print(format!("Measured with {}", id));
+                    }
+                    None => console.print(format!("{} is not a measurement device (see devices)", id)),
+                }
+            }
+            ConsoleCommand::OpenDoor(gp) => {
+                let door = door_query.iter()
+                    .find(|(_, door_gp, blocking)| match gp {
+                        Some(gp) => *door_gp == gp,
+                        None => blocking.is_some(),
+                    });
+                match door {
+                    Some((door, door_gp, _)) => {
+                        doors::open_door(&mut commands, door);
+                        console.print(format!("Opened the door at {}", door_gp));
+                    }
+                    None => console.print("No such door"),
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+pub fn run_spawn_commands(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    grid: Res<GridConfig>,
+    mut command_reader: EventReader<ConsoleCommand>,
+    mut console: ResMut<Console>,
+    mut level: ResMut<Level>,
+    scheme: Res<Scheme>,
+    ) {
+    /*
+     * Spawns devices as the level setup does. They are added
+     * to the level too, so that hints know about them.
+     */
+    for command in command_reader.iter() {
+        if let ConsoleCommand::SpawnDevice(device) = command {
+            let m_id = measurer::spawn_measurement_device(
+                &mut commands, &assets, &grid, *scheme, device.state.clone());
+            if let Some(door_gp) = device.door {
+                doors::spawn_door(
+                    &mut commands, &assets, &grid, door_gp, m_id);
+            }
+            level.devices.push(device.clone());
+            console.print(format!("Spawned device {}", m_id.id()));
+        }
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn update_console(
+    console: Res<Console>,
+    mut panel_query: Query<&mut Visibility, Or<(With<ConsolePanel>, With<ConsoleText>)>>,
+    mut text_query: Query<&mut Text, With<ConsoleText>>,
+    ) {
+    /*
+     * Shows the output and the input line while the console is open
+     */
+    if !console.is_changed() {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        visibility.is_visible = console.open;
+    }
+    let mut lines = console.lines.clone();
+    lines.push(format!("> {}_", console.input));
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gates_need_the_right_tiles() {
+        assert!(ConsoleCommand::parse("switch 0 0 1 0").is_ok());
+        assert!(ConsoleCommand::parse("fourier 0 0 1 0 2 0").is_ok());
+        assert!(ConsoleCommand::parse("switch 0 0").is_err());
+        assert!(ConsoleCommand::parse("mix 0 0 1").is_err());
+        assert!(ConsoleCommand::parse("cycle 0 0 1 0 2 0 3 0 4 0").is_err());
+        assert!(ConsoleCommand::parse("fourier 1 0 1 0").is_err());
+        assert!(ConsoleCommand::parse("diffusion 0 0 1 0 0 0").is_err());
+    }
+}
@@ -1,7 +1,11 @@
+use std::fmt;
 use bevy::prelude::*;
 use bevy_ecs_tilemap::TilePos;
+use serde::{Serialize, Deserialize};
 
-#[derive(Component, PartialEq, Eq, Clone, Copy, Hash, Debug)]
+use crate::settings::Settings;
+
+#[derive(Component, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Hash, Debug, Serialize, Deserialize)]
 pub struct GridPos{
     x: i32, 
     y: i32
@@ -11,17 +15,25 @@ impl GridPos {
     pub fn new(x: i32, y: i32) -> Self {
         GridPos{x, y}
     }
+    pub fn x(&self) -> i32 {
+        self.x
+    }
+    pub fn y(&self) -> i32 {
+        self.y
+    }
+}
+
+impl fmt::Display for GridPos {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "({}, {})", self.x, self.y)
+    }
 }
 
 impl PartialEq<TilePos> for GridPos {
     fn eq(&self, other: &TilePos) -> bool {
-        if self.x >= 0 && self.y >= 0 && 
-                self.x as u32 == other.0 &&
-                self.y as u32 == other.1 {
-            true
-        } else {
-            false
-        }
+        self.x >= 0 && self.y >= 0 &&
+            self.x as u32 == other.0 &&
+            self.y as u32 == other.1
     }
 }
 impl PartialEq<GridPos> for TilePos {
@@ -30,14 +42,105 @@ impl PartialEq<GridPos> for TilePos {
     }
 }
 
-pub fn world_to_grid_coordinates(wc: &Vec2) -> GridPos {
-    GridPos{x: (wc.x / 64.).floor() as i32,
-            y: (wc.y / 64.).floor() as i32}
+// The tile size the art and the sprite layouts were drawn for
+pub const BASE_TILE_SIZE: f32 = 64.;
+
+/*
+ * Resources
+ */
+// The size of a tile in pixels, set in the settings. The art is scaled to it.
+// World coordinates have the bottom left corner of tile (0, 0) at the origin.
+pub struct GridConfig {
+    pub tile_size: f32,
+}
+
+impl Default for GridConfig {
+    fn default() -> Self {
+        GridConfig{ tile_size: BASE_TILE_SIZE }
+    }
+}
+
+impl GridConfig {
+    pub fn world_to_grid(&self, wc: &Vec2) -> GridPos {
+        GridPos{x: (wc.x / self.tile_size).floor() as i32,
+                y: (wc.y / self.tile_size).floor() as i32}
+    }
+    pub fn grid_to_world(&self, gc: &GridPos) -> Vec2 {
+        /*
+         * The center of the tile at gc
+         */
+        Vec2::new((gc.x as f32 + 0.5) * self.tile_size,
+                  (gc.y as f32 + 0.5) * self.tile_size)
+    }
+    pub fn tile(&self) -> Vec2 {
+        Vec2::splat(self.tile_size)
+    }
+    pub fn scale(&self) -> f32 {
+        self.tile_size / BASE_TILE_SIZE
+    }
+    pub fn px(&self, length: f32) -> f32 {
+        /*
+         * A length measured on art for 64 pixel tiles, scaled to the tile
+         * size, so that sprite layouts keep their proportions
+         */
+        length * self.scale()
+    }
+    pub fn art(&self, width: f32, height: f32) -> Vec2 {
+        /*
+         * The size of a sprite drawn width x height pixels
+         * for 64 pixel tiles, scaled to the tile size
+         */
+        Vec2::new(self.px(width), self.px(height))
+    }
 }
-pub fn grid_to_world_coordinates(gc: &GridPos) -> Vec2 {
-    Vec2::new(32. + (gc.x * 64) as f32,
-              32. + (gc.y * 64) as f32)
+
+/*
+ * Systems
+ */
+pub fn apply_tile_size(settings: Res<Settings>, mut grid: ResMut<GridConfig>) {
+    /*
+     * Takes the tile size from the settings before a level is set up,
+     * so that a change in the settings screen shows from the next level on
+     */
+    let tile_size = settings.tile_size() as f32;
+    if grid.tile_size != tile_size {
+        grid.tile_size = tile_size;
+    }
 }
+
 pub fn are_neighbours(p1: &GridPos, p2: &GridPos) -> bool {
     (p1.x - p2.x).abs() <= 1 && (p1.y - p2.y).abs() <= 1
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn grid_world_round_trip() {
+        for tile_size in [32., 64., 128.] {
+            let grid = GridConfig{ tile_size };
+            for (x, y) in [(0, 0), (3, 1), (-1, 0), (-4, -7), (12, 9)] {
+                let gp = GridPos::new(x, y);
+                let center = grid.grid_to_world(&gp);
+                assert_eq!(grid.world_to_grid(&center), gp, "tile size {}", tile_size);
+                // Any point of the tile is on it, up to the edges
+                let half = tile_size / 2. - 0.01;
+                for corner in [Vec2::new(-half, -half), Vec2::new(half, half),
+                               Vec2::new(-half, half), Vec2::new(half, -half)] {
+                    assert_eq!(grid.world_to_grid(&(center + corner)), gp, "tile size {}", tile_size);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tile_size_scales_art() {
+        for tile_size in [32., 64., 128.] {
+            let grid = GridConfig{ tile_size };
+            assert_eq!(grid.grid_to_world(&GridPos::new(0, 0)), Vec2::splat(tile_size / 2.));
+            assert_eq!(grid.art(64., 64.), grid.tile());
+            assert_eq!(grid.px(24.) * 64., 24. * tile_size);
+        }
+    }
+}
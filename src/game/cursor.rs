@@ -0,0 +1,92 @@
+use bevy::prelude::*;
+
+use crate::actions::Action;
+use crate::loading::GameAssets;
+use super::camera::MapBounds;
+use super::coords::*;
+use super::selection::SelectTileEvent;
+use super::replay::Playback;
+use super::animation::GateAnimation;
+
+/*
+ * Components
+ */
+// A grid cursor for selecting tiles with the keyboard or a gamepad
+#[derive(Component)]
+pub struct TileCursor;
+
+pub fn spawn_tile_cursor(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    grid: &GridConfig,
+    gp: GridPos,
+    ) {
+    /*
+     * Spawns the tile cursor. It stays hidden until it is first moved,
+     * so that it does not get in the way of mouse players.
+     */
+    let world_pos = grid.grid_to_world(&gp);
+
+    commands.spawn_bundle(SpriteBundle {
+        texture: assets.select.clone(),
+        sprite: Sprite {
+            color: Color::rgb(1., 0.85, 0.2),
+            custom_size: Some(grid.tile()),
+            ..Default::default()
+        },
+        transform: Transform::from_xyz(world_pos.x, world_pos.y, 21.),
+        visibility: Visibility{ is_visible: false },
+        ..Default::default()
+    })
+    .insert(TileCursor)
+    .insert(gp);
+}
+
+pub fn move_tile_cursor(
+    actions: Res<Input<Action>>,
+    grid: Res<GridConfig>,
+    bounds: Option<Res<MapBounds>>,
+    mut cursor_query: Query<(&mut GridPos, &mut Visibility), With<TileCursor>>,
+    playback: Option<Res<Playback>>,
+    animation: Res<GateAnimation>,
+    mut select_tile_writer: EventWriter<SelectTileEvent>,
+    ) {
+    /*
+     * Moves the tile cursor one tile per press, keeping it on the map,
+     * and selects the tile under it when CursorSelect is pressed.
+     * The GridPos change makes update_transforms move the sprite.
+     */
+    let mut step = (0, 0);
+    if actions.just_pressed(Action::CursorUp) { step.1 += 1; }
+    if actions.just_pressed(Action::CursorDown) { step.1 -= 1; }
+    if actions.just_pressed(Action::CursorLeft) { step.0 -= 1; }
+    if actions.just_pressed(Action::CursorRight) { step.0 += 1; }
+    let select = actions.just_pressed(Action::CursorSelect)
+        && playback.is_none() && !animation.running();
+
+    if step == (0, 0) && !select {
+        return;
+    }
+
+    for (mut gp, mut visibility) in cursor_query.iter_mut() {
+        visibility.is_visible = true;
+
+        if step != (0, 0) {
+            let mut new_gp = GridPos::new(gp.x() + step.0, gp.y() + step.1);
+            // Keep the cursor on the map
+            if let Some(bounds) = &bounds {
+                let min = grid.world_to_grid(&bounds.min);
+                let max = grid.world_to_grid(&(bounds.max - Vec2::ONE));
+                new_gp = GridPos::new(new_gp.x().clamp(min.x(), max.x()),
+                                      new_gp.y().clamp(min.y(), max.y()));
+            }
+            if new_gp != *gp {
+                *gp = new_gp;
+            }
+        }
+
+        if select {
+            select_tile_writer.send(SelectTileEvent{ gp: *gp });
+        }
+    }
+}
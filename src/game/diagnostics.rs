@@ -0,0 +1,139 @@
+use bevy::prelude::*;
+use bevy::log::Level;
+
+use crate::actions::Action;
+use crate::loading::GameAssets;
+use super::event_log::{OperationLog, Outcome};
+use super::player::{Player, QState};
+
+// Log targets, so that each part of the game can be filtered with RUST_LOG
+pub const QUANTUM: &str = "quantum_adventure_game::quantum";
+pub const MEASUREMENT: &str = "quantum_adventure_game::measurement";
+pub const RENDER_SYNC: &str = "quantum_adventure_game::render_sync";
+
+/*
+ * Components
+ */
+#[derive(Component)]
+pub struct DebugOverlay;
+
+/*
+ * Resources
+ */
+// How much the game logs. It can be changed while playing,
+// unlike the filter of the log plugin.
+pub struct Verbosity {
+    pub level: Level,
+}
+
+impl Default for Verbosity {
+    fn default() -> Self {
+        Verbosity{ level: Level::INFO }
+    }
+}
+
+impl Verbosity {
+    pub fn allows(&self, level: Level) -> bool {
+        level <= self.level
+    }
+}
+
+pub fn spawn_debug_overlay(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    ) {
+    /*
+     * Spawns the overlay showing the verbosity and the player state,
+     * only visible when logging more than usual
+     */
+    commands.spawn_bundle(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                left: Val::Percent(35.0),
+                top: Val::Px(10.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: assets.font.clone(),
+                font_size: 18.0,
+                color: Color::rgb(1.0, 0.6, 0.6),
+            },
+            Default::default(),
+        ),
+        visibility: Visibility{ is_visible: false },
+        ..Default::default()
+    })
+    .insert(DebugOverlay);
+}
+
+/*
+ * Systems
+ */
+pub fn cycle_verbosity(actions: Res<Input<Action>>, mut verbosity: ResMut<Verbosity>) {
+    if actions.just_pressed(Action::CycleVerbosity) {
+        verbosity.level = match verbosity.level {
+            Level::INFO => Level::DEBUG,
+            Level::DEBUG => Level::TRACE,
+            _ => Level::INFO,
+        };
+        info!("Logging up to {}", verbosity.level);
+    }
+}
+
+pub fn log_operations(
+    log: Res<OperationLog>,
+    verbosity: Res<Verbosity>,
+    player_query: Query<&QState, With<Player>>,
+    mut logged: Local<usize>,
+    ) {
+    /*
+     * Logs the operations as they are added to the operation log
+     */
+    if !log.is_changed() {
+        return;
+    }
+    // A new level was started
+    if log.entries.len() < *logged {
+        *logged = 0;
+    }
+    for entry in log.entries[*logged..].iter() {
+        match entry.outcome {
+            Outcome::Succeeded | Outcome::Failed => info!(target: MEASUREMENT, "{}", entry),
+            _ if verbosity.allows(Level::DEBUG) => debug!(target: QUANTUM, "{}", entry),
+            _ => (),
+        }
+    }
+    if *logged < log.entries.len() && verbosity.allows(Level::TRACE) {
+        for state in player_query.iter() {
+            trace!(target: QUANTUM, "State {}", state);
+        }
+    }
+    *logged = log.entries.len();
+}
+
+pub fn update_debug_overlay(
+    verbosity: Res<Verbosity>,
+    player_query: Query<&QState, With<Player>>,
+    changed_query: Query<Entity, (Changed<QState>, With<Player>)>,
+    mut overlay_query: Query<(&mut Text, &mut Visibility), With<DebugOverlay>>,
+    ) {
+    /*
+     * Shows the verbosity and the exact player state
+     */
+    if !verbosity.is_changed() && changed_query.is_empty() {
+        return;
+    }
+    let mut lines = vec![format!("Logging up to {}", verbosity.level)];
+    for state in player_query.iter() {
+        lines.push(format!("{:.4}", state));
+    }
+    for (mut text, mut visibility) in overlay_query.iter_mut() {
+        visibility.is_visible = verbosity.allows(Level::DEBUG);
+        text.sections[0].value = lines.join("\n");
+    }
+}
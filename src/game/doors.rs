@@ -1,14 +1,12 @@
 use bevy::prelude::*;
 
+use crate::loading::GameAssets;
 use super::coords::*;
 use super::operations::{Blocking, MeasureSuccessEvent};
-use num::complex;
-#[allow(non_camel_case_types)]
-type c32 = complex::Complex32;
 
 #[derive(Component)]
 pub struct OpenableByMeasurement{
-    measurement_device_entity: Entity
+    pub measurement_device_entity: Entity
 }
 
 #[derive(Component)]
@@ -18,8 +16,8 @@ pub struct AnimateOnce;
 
 pub fn spawn_door(
     commands: &mut Commands,
-    asset_server: &Res<AssetServer>,
-    texture_atlases: &mut ResMut<Assets<TextureAtlas>>,
+    assets: &GameAssets,
+    grid: &GridConfig,
     gp: GridPos,
     measurement_device_entity: Entity,
     ) {
@@ -27,15 +25,15 @@ pub fn spawn_door(
      * Spawns a door that opens when successful measurement
      * by the given entity is done.
      */
-    let world_pos = grid_to_world_coordinates(&gp);
-
-    let texture_handle = asset_server.load("sprites/door_anim.png");
-    let texture_atlas = TextureAtlas::from_grid(texture_handle, Vec2::new(64.0, 64.0), 10, 1);
-    let texture_atlas_handle = texture_atlases.add(texture_atlas);
+    let world_pos = grid.grid_to_world(&gp);
 
     commands.spawn_bundle(SpriteSheetBundle{
-        texture_atlas: texture_atlas_handle,
-        sprite: TextureAtlasSprite::new(0),
+        texture_atlas: assets.door.clone(),
+        sprite: TextureAtlasSprite{
+            index: 0,
+            custom_size: Some(grid.tile()),
+            ..Default::default()
+        },
         transform: Transform::from_xyz(world_pos.x, world_pos.y, 1.),
         ..Default::default()
     })
@@ -45,6 +43,15 @@ pub fn spawn_door(
     .insert(gp);
 }
 
+pub fn open_door(commands: &mut Commands, door: Entity) {
+    /*
+     * Unblocks the door and starts its opening animation
+     */
+    commands.entity(door)
+        .remove::<Blocking>()
+        .insert(Timer::from_seconds(0.1, true));
+}
+
 pub fn door_opening_system(
     mut commands: Commands,
     mut measurement_success_reader: EventReader<MeasureSuccessEvent>,
@@ -56,9 +63,7 @@ pub fn door_opening_system(
     for event in measurement_success_reader.iter() {
         for (door_entity, openable) in door_query.iter() {
             if openable.measurement_device_entity == event.entity {
-                commands.entity(door_entity)
-                    .remove::<Blocking>()
-                    .insert(Timer::from_seconds(0.1, true));
+                open_door(&mut commands, door_entity);
             }
         }
     }
@@ -79,7 +84,7 @@ pub fn sprite_animation(
                 commands.entity(entity)
                     .remove::<Timer>();
             } else {
-                sprite.index = sprite.index + 1;
+                sprite.index += 1;
             }
         }
     }
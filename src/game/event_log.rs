@@ -0,0 +1,238 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::time::{SystemTime, UNIX_EPOCH};
+use bevy::prelude::*;
+use num::complex;
+use serde::Serialize;
+
+use crate::actions::Action;
+use crate::loading::GameAssets;
+use super::coords::*;
+use super::level::{CurrentLevel, Gate};
+use super::player::QState;
+use super::solver::Move;
+#[allow(non_camel_case_types)]
+type c32 = complex::Complex32;
+
+// Directory, relative to the working directory, that logs are exported to
+const LOG_DIR: &str = "logs";
+// Lines of the log shown at a time
+const VISIBLE_LINES: usize = 12;
+
+/*
+ * Components
+ */
+#[derive(Component)]
+pub struct LogPanel;
+#[derive(Component)]
+pub struct LogText;
+
+/*
+ * Resources
+ */
+#[derive(Clone, Copy, Serialize)]
+pub enum Outcome {
+    Applied,
+    Succeeded,
+    Failed,
+    Undone,
+}
+
+#[derive(Clone, Serialize)]
+pub struct LogEntry {
+    pub turn: usize,
+    pub operation: Move,
+    pub outcome: Outcome,
+    // How likely the outcome was
+    pub probability: f32,
+    // The factors at the tiles afterwards
+    pub factors: Vec<c32>,
+}
+
+impl fmt::Display for LogEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}. ", self.turn)?;
+        match self.outcome {
+            Outcome::Undone => return write!(f, "Undo {}", self.operation.gate.name()),
+            Outcome::Applied => write!(f, "{}", self.operation)?,
+            Outcome::Succeeded => write!(f, "{}: success", self.operation)?,
+            Outcome::Failed => write!(f, "{}: failure", self.operation)?,
+        }
+        if self.probability < 1. {
+            write!(f, " (p = {:.2})", self.probability)?;
+        }
+        if !self.factors.is_empty() {
+            let factors: Vec<String> = self.factors.iter()
+                .map(|factor| format!("{:.2}", factor))
+                .collect();
+            write!(f, " -> {}", factors.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+// Everything the player did in the level, oldest first
+#[derive(Default, Serialize)]
+pub struct OperationLog {
+    pub entries: Vec<LogEntry>,
+}
+
+impl OperationLog {
+    pub fn push(
+        &mut self,
+        gate: Gate,
+        tiles: &[GridPos],
+        outcome: Outcome,
+        probability: f32,
+        factors: Vec<c32>,
+        ) {
+        self.entries.push(LogEntry{
+            turn: self.entries.len() + 1,
+            operation: Move{ gate, tiles: tiles.to_vec() },
+            outcome,
+            probability,
+            factors,
+        });
+    }
+
+    pub fn push_applied(&mut self, gate: Gate, tiles: &[GridPos], state: &QState) {
+        /*
+         * Logs a gate other than a measurement, which always applies,
+         * with the factors it left on its tiles
+         */
+        self.push(gate, tiles, Outcome::Applied, 1., state.factors(tiles));
+    }
+
+    fn export(&self, level_name: &str) -> Result<PathBuf, String> {
+        /*
+         * Writes the log as text and as JSON, named after the level and
+         * the time, and returns the path without extension
+         */
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        let path = PathBuf::from(LOG_DIR).join(format!("{}-{}", level_name, seconds));
+
+        let text: Vec<String> = self.entries.iter()
+            .map(LogEntry::to_string)
+            .collect();
+        let json = serde_json::to_string_pretty(self).map_err(|e| e.to_string())?;
+        fs::create_dir_all(LOG_DIR).map_err(|e| e.to_string())?;
+        fs::write(path.with_extension("txt"), text.join("\n")).map_err(|e| e.to_string())?;
+        fs::write(path.with_extension("json"), json).map_err(|e| e.to_string())?;
+        Ok(path)
+    }
+}
+
+#[derive(Default)]
+pub struct LogView {
+    // How many lines the view is scrolled up from the newest
+    scroll: usize,
+    hidden: bool,
+    // Where the log was last exported to, or why it could not be
+    export_note: Option<String>,
+}
+
+pub fn spawn_log_panel(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    ) {
+    /*
+     * Spawns the panel showing the end of the log.
+     * Its text is filled in by update_log_panel.
+     */
+    commands.spawn_bundle(NodeBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                right: Val::Px(10.0),
+                top: Val::Px(10.0),
+                ..Default::default()
+            },
+            padding: Rect::all(Val::Px(8.0)),
+            ..Default::default()
+        },
+        color: Color::rgba(0., 0., 0., 0.5).into(),
+        ..Default::default()
+    })
+    .insert(LogPanel)
+    .with_children(|parent| {
+        parent.spawn_bundle(TextBundle {
+            text: Text::with_section(
+                "",
+                TextStyle {
+                    font: assets.font.clone(),
+                    font_size: 18.0,
+                    color: Color::rgb(0.9, 0.9, 0.9),
+                },
+                Default::default(),
+            ),
+            ..Default::default()
+        })
+        .insert(LogText);
+    });
+}
+
+/*
+ * Systems
+ */
+pub fn log_controls(
+    actions: Res<Input<Action>>,
+    log: Res<OperationLog>,
+    current_level: Res<CurrentLevel>,
+    mut view: ResMut<LogView>,
+    ) {
+    /*
+     * Shows, hides, scrolls and exports the log
+     */
+    if actions.just_pressed(Action::ToggleLog) {
+        view.hidden = !view.hidden;
+    }
+    let max_scroll = log.entries.len().saturating_sub(VISIBLE_LINES);
+    if actions.just_pressed(Action::ScrollLogUp) {
+        view.scroll = (view.scroll + 1).min(max_scroll);
+    }
+    if actions.just_pressed(Action::ScrollLogDown) {
+        view.scroll = view.scroll.saturating_sub(1);
+    }
+    if actions.just_pressed(Action::ExportLog) {
+        view.export_note = Some(match log.export(&current_level.name) {
+            Ok(path) => format!("Exported to {}.txt/.json", path.display()),
+            Err(e) => format!("Could not export the log: {}", e),
+        });
+    }
+}
+
+#[allow(clippy::type_complexity)]
+pub fn update_log_panel(
+    log: Res<OperationLog>,
+    view: Res<LogView>,
+    mut panel_query: Query<&mut Visibility, Or<(With<LogPanel>, With<LogText>)>>,
+    mut text_query: Query<&mut Text, With<LogText>>,
+    ) {
+    /*
+     * Shows the lines of the log scrolled to
+     */
+    if !log.is_changed() && !view.is_changed() {
+        return;
+    }
+    for mut visibility in panel_query.iter_mut() {
+        visibility.is_visible = !view.hidden;
+    }
+
+    let end = log.entries.len().saturating_sub(view.scroll);
+    let start = end.saturating_sub(VISIBLE_LINES);
+    let mut lines: Vec<String> = log.entries[start..end].iter()
+        .map(LogEntry::to_string)
+        .collect();
+    if lines.is_empty() {
+        lines.push("Nothing done yet".to_string());
+    }
+    if let Some(note) = &view.export_note {
+        lines.push(note.clone());
+    }
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
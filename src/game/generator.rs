@@ -0,0 +1,246 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::str::FromStr;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use num::complex;
+
+use super::coords::*;
+use super::level::{DeviceSpec, Gate, GateInventory, Level};
+use super::player::QState;
+use super::solver::{self, SearchLimits};
+#[allow(non_camel_case_types)]
+type c32 = complex::Complex32;
+
+// Layouts to try before giving up on a code
+const ATTEMPTS: usize = 100;
+// Situations the solver may look at per layout, to keep generation quick
+const NODES_PER_ATTEMPT: usize = 20_000;
+// The sizes levels are generated in, from the smallest that leaves room
+// on both sides of the door to the largest that lays out quickly
+const MIN_SIZE: (u32, u32) = (5, 3);
+const MAX_SIZE: (u32, u32) = (64, 64);
+
+// Everything a generated level is made from, shared as
+// a short code like 9x5-8-2f1c, i.e. size, operations and seed
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct LevelCode {
+    pub width: u32,
+    pub height: u32,
+    // The generated level can be solved in at most this many operations
+    pub max_operations: usize,
+    pub seed: u64,
+}
+
+impl LevelCode {
+    pub fn random(width: u32, height: u32, max_operations: usize) -> Self {
+        LevelCode{ width, height, max_operations, seed: rand::random::<u32>() as u64 }
+    }
+}
+
+impl Default for LevelCode {
+    fn default() -> Self {
+        LevelCode::random(9, 5, 8)
+    }
+}
+
+impl fmt::Display for LevelCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}x{}-{}-{:x}", self.width, self.height, self.max_operations, self.seed)
+    }
+}
+
+impl FromStr for LevelCode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("{} is not a level code", s);
+        let parts: Vec<&str> = s.split('-').collect();
+        let (size, operations, seed) = match parts[..] {
+            [size, operations, seed] => (size, operations, seed),
+            _ => return Err(invalid()),
+        };
+        let (width, height) = size.split_once('x').ok_or_else(invalid)?;
+        Ok(LevelCode{
+            width: width.parse().map_err(|_| invalid())?,
+            height: height.parse().map_err(|_| invalid())?,
+            max_operations: operations.parse().map_err(|_| invalid())?,
+            seed: u64::from_str_radix(seed, 16).map_err(|_| invalid())?,
+        })
+    }
+}
+
+pub fn generate(code: &LevelCode) -> Result<Level, String> {
+    /*
+     * Lays out levels from the seed until the solver proves one
+     * can be solved within the operations of the code.
+     * The same code always gives the same level.
+     */
+    if !(MIN_SIZE.0..=MAX_SIZE.0).contains(&code.width)
+        || !(MIN_SIZE.1..=MAX_SIZE.1).contains(&code.height) {
+        return Err(format!("{}: generated levels are from {}x{} up to {}x{}",
+                           code, MIN_SIZE.0, MIN_SIZE.1, MAX_SIZE.0, MAX_SIZE.1));
+    }
+    let mut rng = StdRng::seed_from_u64(code.seed);
+    let limits = SearchLimits{
+        max_operations: code.max_operations,
+        max_nodes: NODES_PER_ATTEMPT,
+        ..Default::default()
+    };
+    for _ in 0..ATTEMPTS {
+        let level = layout(code, &mut rng);
+        if walkable(&level) && solver::solve(&level, &limits).is_ok() {
+            return Ok(level);
+        }
+    }
+    Err(format!("{}: no solvable layout found", code))
+}
+
+fn layout(code: &LevelCode, rng: &mut StdRng) -> Level {
+    /*
+     * A wall splits the map in two with a door in it. The player
+     * starts on the left, where a measurement device opens the door,
+     * and the goal is on the right. Stray walls are scattered about.
+     */
+    let (width, height) = (code.width as i32, code.height as i32);
+    let middle = width / 2;
+    let door = GridPos::new(middle, rng.gen_range(0..height));
+    let mut walls: Vec<GridPos> = (0..height)
+        .map(|y| GridPos::new(middle, y))
+        .filter(|gp| *gp != door)
+        .collect();
+
+    let left = |rng: &mut StdRng| GridPos::new(rng.gen_range(0..middle), rng.gen_range(0..height));
+    let player_gp = left(rng);
+    let goal = GridPos::new(rng.gen_range(middle + 1..width), rng.gen_range(0..height));
+
+    // Two neighbouring tiles, with a relative sign the player has to get right
+    let first = left(rng);
+    let second = loop {
+        let gp = GridPos::new(first.x() + rng.gen_range(-1..=1), first.y() + rng.gen_range(-1..=1));
+        if gp != first && gp.x() >= 0 && gp.x() < middle && gp.y() >= 0 && gp.y() < height {
+            break gp;
+        }
+    };
+    let sign = if rng.gen_bool(0.5) { 1. } else { -1. };
+    let device_state = QState{ map: HashMap::from([
+        (first, c32::new(1. / 2_f32.sqrt(), 0.)),
+        (second, c32::new(sign / 2_f32.sqrt(), 0.)),
+    ]) };
+
+    let keep_free = [player_gp, goal, first, second, door];
+    for _ in 0..(width * height) / 8 {
+        let gp = GridPos::new(rng.gen_range(0..width), rng.gen_range(0..height));
+        if !keep_free.contains(&gp) && !walls.contains(&gp) {
+            walls.push(gp);
+        }
+    }
+
+    Level{
+        width: code.width,
+        height: code.height,
+        player: QState{ map: HashMap::from([(player_gp, c32::new(1., 0.))]) },
+        walls,
+        devices: vec![DeviceSpec{ state: device_state, door: Some(door) }],
+        goal: Some(goal),
+        gates: GateInventory{ uses: BTreeMap::from([
+            (Gate::Switch, None),
+            (Gate::Mix, None),
+            (Gate::Measure, None),
+        ]) },
+        hints: None,
+    }
+}
+
+fn walkable(level: &Level) -> bool {
+    /*
+     * Whether the player, the device and the goal are all connected
+     * through tiles without walls. Cheap enough to weed out
+     * hopeless layouts before the solver gets them.
+     */
+    let walls: HashSet<&GridPos> = level.walls.iter().collect();
+    let free = |gp: &GridPos| gp.x() >= 0 && gp.y() >= 0
+        && (gp.x() as u32) < level.width && (gp.y() as u32) < level.height
+        && !walls.contains(gp);
+
+    let mut reached: HashSet<GridPos> = level.player.map.keys().copied().collect();
+    let mut frontier: Vec<GridPos> = reached.iter().copied().collect();
+    while let Some(gp) = frontier.pop() {
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let next = GridPos::new(gp.x() + dx, gp.y() + dy);
+                if free(&next) && reached.insert(next) {
+                    frontier.push(next);
+                }
+            }
+        }
+    }
+    level.goal.iter()
+        .chain(level.devices.iter().flat_map(|device| device.state.map.keys()))
+        .all(|gp| reached.contains(gp))
+}
+
+pub fn print_level(args: &[String]) -> i32 {
+    /*
+     * Prints the level of the given code, or of a random one,
+     * in the level file format. Returns the exit code.
+     */
+    let code = match args.first() {
+        Some(arg) => match arg.parse::<LevelCode>() {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("{}", e);
+                return 1;
+            }
+        },
+        None => LevelCode::default(),
+    };
+    let level = generate(&code)
+        .and_then(|level| ron::ser::to_string_pretty(
+                &level, ron::ser::PrettyConfig::new().depth_limit(2))
+                  .map_err(|e| e.to_string()));
+    match level {
+        Ok(level) => {
+            println!("// Generated from level code {}\n{}", code, level);
+            0
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            1
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn code_round_trip() {
+        let code: LevelCode = "9x5-8-2f1c".parse().unwrap();
+        assert_eq!(code, LevelCode{ width: 9, height: 5, max_operations: 8, seed: 0x2f1c });
+        assert_eq!(code.to_string(), "9x5-8-2f1c");
+    }
+
+    #[test]
+    fn same_code_same_level() {
+        let code: LevelCode = "9x5-8-1".parse().unwrap();
+        // States are written sorted, unlike their maps
+        let print = |level: Level| format!(
+            "{}x{} {} {:?} {:?} {:?} {}",
+            level.width, level.height, level.player, level.walls,
+            level.devices.iter()
+                .map(|device| format!("{} {:?}", device.state, device.door))
+                .collect::<Vec<_>>(),
+            level.goal, ron::to_string(&level.gates).unwrap());
+        assert_eq!(print(generate(&code).unwrap()), print(generate(&code).unwrap()));
+    }
+
+    #[test]
+    fn sizes_are_limited() {
+        for code in ["4x5-8-1", "9x2-8-1", "65x5-8-1", "100000x100000-12-1"] {
+            let code: LevelCode = code.parse().unwrap();
+            assert!(generate(&code).is_err(), "{} was generated", code);
+        }
+    }
+}
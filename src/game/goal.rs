@@ -0,0 +1,110 @@
+use bevy::prelude::*;
+
+use crate::loading::GameAssets;
+use super::coords::*;
+use super::player::{Player, QState};
+
+// The player has reached the goal once the probability of
+// being there is this close to one
+const GOAL_TOLERANCE: f32 = 1e-3;
+
+/*
+ * Components
+ */
+#[derive(Component)]
+pub struct Goal;
+#[derive(Component)]
+pub struct LevelCompleteBanner;
+
+pub fn spawn_goal(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    grid: &GridConfig,
+    gp: GridPos,
+    ) {
+    /*
+     * Spawns the goal, a swaying flower
+     */
+    let world_pos = grid.grid_to_world(&gp);
+
+
+    commands.spawn_bundle(SpriteSheetBundle{
+        texture_atlas: assets.flower.clone(),
+        sprite: TextureAtlasSprite{
+            index: 0,
+            custom_size: Some(grid.tile()),
+            ..Default::default()
+        },
+        transform: Transform::from_xyz(world_pos.x, world_pos.y, 1.),
+        ..Default::default()
+    })
+    .insert(Goal)
+    .insert(Timer::from_seconds(0.15, true))
+    .insert(gp);
+}
+
+pub fn reached(state: &QState, goal: &GridPos) -> bool {
+    state.get(goal).norm_sqr() >= 1. - GOAL_TOLERANCE
+}
+
+/*
+ * Systems
+ */
+pub fn goal_animation(
+    time: Res<Time>,
+    texture_atlases: Res<Assets<TextureAtlas>>,
+    mut query: Query<(&mut Timer, &mut TextureAtlasSprite, &Handle<TextureAtlas>), With<Goal>>,
+    ) {
+    /*
+     * Loops through the frames of the goal
+     */
+    for (mut timer, mut sprite, texture_atlas_handle) in query.iter_mut() {
+        timer.tick(time.delta());
+        if timer.just_finished() {
+            if let Some(texture_atlas) = texture_atlases.get(texture_atlas_handle) {
+                sprite.index = (sprite.index + 1) % texture_atlas.textures.len();
+            }
+        }
+    }
+}
+
+pub fn check_goal(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    player_query: Query<&QState, (Changed<QState>, With<Player>)>,
+    goal_query: Query<&GridPos, With<Goal>>,
+    banner_query: Query<Entity, With<LevelCompleteBanner>>,
+    ) {
+    /*
+     * Congratulates the player once they are at the goal with certainty
+     */
+    if !banner_query.is_empty() {
+        return;
+    }
+    for state in player_query.iter() {
+        if goal_query.iter().any(|goal| reached(state, goal)) {
+            commands.spawn_bundle(TextBundle {
+                style: Style {
+                    position_type: PositionType::Absolute,
+                    position: Rect {
+                        left: Val::Px(10.0),
+                        top: Val::Px(10.0),
+                        ..Default::default()
+                    },
+                    ..Default::default()
+                },
+                text: Text::with_section(
+                    "Level complete!",
+                    TextStyle {
+                        font: assets.font.clone(),
+                        font_size: 40.0,
+                        color: Color::rgb(1., 0.85, 0.2),
+                    },
+                    Default::default(),
+                ),
+                ..Default::default()
+            })
+            .insert(LevelCompleteBanner);
+        }
+    }
+}
@@ -0,0 +1,260 @@
+use bevy::prelude::*;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use futures_lite::future;
+
+use crate::actions::Action;
+use crate::loading::GameAssets;
+use super::coords::*;
+use super::doors::OpenableByMeasurement;
+use super::level::{Gate, GateInventory, Level};
+use super::operations::Blocking;
+use super::player::{Player, QState};
+use super::solver::{self, Move, SearchLimits, Situation, Solution, SolveError};
+
+/*
+ * Components
+ */
+#[derive(Component)]
+pub struct HintMarker;
+#[derive(Component)]
+pub struct HintText;
+// A solver search for a hint, running in the background,
+// and the state it starts from
+#[derive(Component)]
+pub struct HintSearch {
+    task: Task<Result<Solution, SolveError>>,
+    state: QState,
+}
+
+/*
+ * Resources
+ */
+// The hints left in the level, None for unlimited
+pub struct HintBudget {
+    pub remaining: Option<u32>,
+}
+
+pub fn spawn_hint_text(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    ) {
+    /*
+     * Spawns the text explaining the current hint,
+     * empty until a hint is asked for
+     */
+    commands.spawn_bundle(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                right: Val::Px(10.0),
+                bottom: Val::Px(10.0),
+                ..Default::default()
+            },
+            max_size: Size::new(Val::Px(500.0), Val::Undefined),
+            ..Default::default()
+        },
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: assets.font.clone(),
+                font_size: 24.0,
+                color: Color::rgb(0.6, 0.9, 1.0),
+            },
+            Default::default(),
+        ),
+        ..Default::default()
+    })
+    .insert(HintText);
+}
+
+fn list_tiles(tiles: &[GridPos]) -> String {
+    tiles.iter()
+        .map(|gp| gp.to_string())
+        .collect::<Vec<_>>()
+        .join(", then ")
+}
+
+fn explain(mv: &Move, state: &QState, level: &Level) -> String {
+    /*
+     * A short explanation of what the move does
+     */
+    let tiles = list_tiles(&mv.tiles);
+    match mv.gate {
+        Gate::Switch => format!(
+            "Switch {}: the two tiles swap amplitudes.", tiles),
+        Gate::Mix => format!(
+            "Mix {}. Order matters: the first tile gets (a - b)/\u{221a}2 \
+             and the second (a + b)/\u{221a}2.", tiles),
+        Gate::Cycle => format!(
+            "Cycle {}: each amplitude moves on to the next tile, \
+             and the last one back to the first.", tiles),
+        Gate::Fourier => format!(
+            "Fourier transform {}: every tile gets a share of every amplitude, \
+             with a phase that depends on the order.", tiles),
+        Gate::Diffusion => format!(
+            "Diffuse {}: the amplitudes are reflected about their mean.", tiles),
+        Gate::Measure => {
+            let prob = level.devices.iter()
+                .find(|device| device.state.map.contains_key(&mv.tiles[0]))
+                .map_or(0., |device| state.scal_prod(&device.state).norm_sqr());
+            format!("Measure the device at {}: it succeeds with probability {:.0}%.",
+                    tiles, prob * 100.)
+        }
+    }
+}
+
+/*
+ * Systems
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn give_hint(
+    mut commands: Commands,
+    pool: Res<AsyncComputeTaskPool>,
+    actions: Res<Input<Action>>,
+    level: Res<Level>,
+    inventory: Res<GateInventory>,
+    budget: Res<HintBudget>,
+    player_query: Query<&QState, With<Player>>,
+    door_query: Query<(&GridPos, Option<&Blocking>), With<OpenableByMeasurement>>,
+    marker_query: Query<Entity, With<HintMarker>>,
+    search_query: Query<(), With<HintSearch>>,
+    mut text_query: Query<&mut Text, With<HintText>>,
+    ) {
+    /*
+     * Starts the solver on the best next operation from where the player is.
+     * It runs in the background, so that long searches do not stall the game,
+     * and show_hint picks up what it finds.
+     */
+    if !actions.just_pressed(Action::Hint) || !search_query.is_empty() {
+        return;
+    }
+    let message = if budget.remaining == Some(0) {
+        "No hints left in this level."
+    } else {
+        "Thinking..."
+    };
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = message.to_string();
+    }
+    if budget.remaining == Some(0) {
+        return;
+    }
+    for entity in marker_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    let state = player_query.single().clone();
+    // A door is open once it no longer blocks its tile
+    let open_doors = level.devices.iter()
+        .map(|device| device.door.map_or(false, |door_gp| door_query.iter()
+             .any(|(gp, blocking)| *gp == door_gp && blocking.is_none())))
+        .collect();
+    let situation = Situation{
+        state: state.clone(),
+        open_doors,
+        inventory: inventory.clone(),
+    };
+    let level = level.clone();
+    let task = pool.spawn(async move {
+        solver::solve_from(&level, situation, &SearchLimits::default())
+    });
+    commands.spawn().insert(HintSearch{ task, state });
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn show_hint(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    grid: Res<GridConfig>,
+    level: Res<Level>,
+    mut budget: ResMut<HintBudget>,
+    mut search_query: Query<(Entity, &mut HintSearch)>,
+    mut text_query: Query<&mut Text, With<HintText>>,
+    ) {
+    /*
+     * Once the solver is done, marks the tiles to select
+     * for the operation it found and explains it
+     */
+    for (entity, mut search) in search_query.iter_mut() {
+        let result = match future::block_on(future::poll_once(&mut search.task)) {
+            Some(result) => result,
+            None => continue,
+        };
+        commands.entity(entity).despawn();
+
+        let explanation = match result {
+            Ok(solution) => match solution.moves.first() {
+                Some(mv) => {
+                    if let Some(remaining) = budget.remaining.as_mut() {
+                        *remaining -= 1;
+                    }
+                    for (i, gp) in mv.tiles.iter().enumerate() {
+                        spawn_hint_marker(&mut commands, &assets, &grid, *gp, i);
+                    }
+                    format!("{}\n{} operations to go, works out with probability {:.0}%.{}",
+                            explain(mv, &search.state, &level),
+                            solution.moves.len(),
+                            solution.probability * 100.,
+                            match budget.remaining {
+                                Some(remaining) => format!(" {} hints left.", remaining),
+                                None => String::new(),
+                            })
+                }
+                None => "Nothing left to do here.".to_string(),
+            },
+            Err(e) => format!("No hint from here: {}. Try undoing.", e),
+        };
+        for mut text in text_query.iter_mut() {
+            text.sections[0].value = explanation.clone();
+        }
+    }
+}
+
+fn spawn_hint_marker(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    grid: &GridConfig,
+    gp: GridPos,
+    order: usize,
+    ) {
+    /*
+     * Marks a tile to select, fading with the order it should be selected in
+     */
+    let world_pos = grid.grid_to_world(&gp);
+    commands.spawn_bundle(SpriteBundle {
+            texture: assets.select.clone(),
+            sprite: Sprite {
+                color: Color::rgba(0.4, 0.8, 1., 1. - 0.15 * order as f32),
+                custom_size: Some(grid.tile()),
+                ..Default::default()
+            },
+            transform: Transform::from_xyz(world_pos.x, world_pos.y, 22.),
+            ..Default::default()
+        })
+        .insert(HintMarker)
+        .insert(gp);
+}
+
+#[allow(clippy::type_complexity)]
+pub fn clear_hint(
+    mut commands: Commands,
+    player_query: Query<&QState, (Changed<QState>, With<Player>)>,
+    marker_query: Query<Entity, Or<(With<HintMarker>, With<HintSearch>)>>,
+    mut text_query: Query<&mut Text, With<HintText>>,
+    ) {
+    /*
+     * The hint is stale as soon as the player state changes.
+     * A search still running is dropped, which cancels it.
+     */
+    if player_query.is_empty() {
+        return;
+    }
+    for entity in marker_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+    for mut text in text_query.iter_mut() {
+        if !text.sections[0].value.is_empty() {
+            text.sections[0].value.clear();
+        }
+    }
+}
@@ -0,0 +1,270 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use bevy::prelude::*;
+use bevy::asset::FileAssetIo;
+use bevy::tasks::{AsyncComputeTaskPool, Task};
+use futures_lite::future;
+use serde::{Serialize, Deserialize};
+
+use crate::AppState;
+use crate::actions::Action;
+use crate::loading::GameAssets;
+use super::coords::*;
+use super::generator::{LevelCode, generate};
+use super::player::QState;
+
+/*
+ * Gates
+ */
+// The most tiles a gate acts on
+pub const MAX_GATE_TILES: usize = 4;
+
+#[derive(PartialEq, Eq, PartialOrd, Ord, Hash, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum Gate {
+    Switch,
+    Mix,
+    Cycle,
+    Fourier,
+    Diffusion,
+    Measure,
+}
+
+impl Gate {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Gate::Switch => "Switch",
+            Gate::Mix => "Mix",
+            Gate::Cycle => "Cycle",
+            Gate::Fourier => "Fourier",
+            Gate::Diffusion => "Diffusion",
+            Gate::Measure => "Measure",
+        }
+    }
+
+    pub fn action(&self) -> Action {
+        match self {
+            Gate::Switch => Action::Switch,
+            Gate::Mix => Action::Mix,
+            Gate::Cycle => Action::Cycle,
+            Gate::Fourier => Action::Fourier,
+            Gate::Diffusion => Action::Diffusion,
+            Gate::Measure => Action::Measure,
+        }
+    }
+
+    pub fn check_tiles(&self, tiles: &[GridPos]) -> Result<(), String> {
+        /*
+         * Whether the gate can act on the tiles: the pair gates act on two,
+         * the k-tile gates on two up to MAX_GATE_TILES and a measurement
+         * on the tiles of its device. A tile given twice would break unitarity.
+         */
+        let count_ok = match self {
+            Gate::Switch | Gate::Mix => tiles.len() == 2,
+            Gate::Cycle | Gate::Fourier | Gate::Diffusion =>
+                (2..=MAX_GATE_TILES).contains(&tiles.len()),
+            Gate::Measure => !tiles.is_empty(),
+        };
+        if !count_ok {
+            return Err(format!("{} does not act on {} tiles", self.name(), tiles.len()));
+        }
+        match tiles.iter().enumerate().find(|(i, gp)| tiles[..*i].contains(gp)) {
+            Some((_, gp)) => Err(format!("{} is given twice", gp)),
+            None => Ok(()),
+        }
+    }
+}
+
+/*
+ * Components
+ */
+#[derive(Component)]
+pub struct LevelLoadingText;
+
+/*
+ * Resources
+ */
+// The level being read or generated in the background
+pub struct LevelLoad {
+    task: Task<Result<Level, String>>,
+}
+
+// The gates a level allows and how many times each can be used.
+// None means unlimited uses, a gate missing from the map is unavailable.
+#[derive(Clone, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct GateInventory {
+    pub uses: BTreeMap<Gate, Option<u32>>,
+}
+
+impl GateInventory {
+    pub fn try_use(&mut self, gate: Gate) -> bool {
+        /*
+         * Uses up one use of gate, if there is one left
+         */
+        match self.uses.get_mut(&gate) {
+            Some(None) => true,
+            Some(Some(n)) if *n > 0 => {
+                *n -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn refund(&mut self, gate: Gate) {
+        if let Some(Some(n)) = self.uses.get_mut(&gate) {
+            *n += 1;
+        }
+    }
+}
+
+// The name of the level file (without extension) to play
+pub struct CurrentLevel {
+    pub name: String,
+}
+
+impl Default for CurrentLevel {
+    fn default() -> Self {
+        CurrentLevel{ name: "first".to_string() }
+    }
+}
+
+/*
+ * Level files
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub struct DeviceSpec {
+    // The state a successful measurement projects onto
+    pub state: QState,
+    // The door a successful measurement opens, if any
+    pub door: Option<GridPos>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Level {
+    // Size of the map in tiles
+    pub width: u32,
+    pub height: u32,
+    pub player: QState,
+    #[serde(default)]
+    pub walls: Vec<GridPos>,
+    pub devices: Vec<DeviceSpec>,
+    // The level is won when the player is at the goal with certainty
+    #[serde(default)]
+    pub goal: Option<GridPos>,
+    pub gates: GateInventory,
+    // How many hints the level gives, None for unlimited
+    #[serde(default)]
+    pub hints: Option<u32>,
+}
+
+impl Level {
+    pub fn load(name: &str) -> Result<Self, String> {
+        /*
+         * Generates the level if name is a level code,
+         * and otherwise reads assets/levels/<name>.ron
+         */
+        if let Ok(code) = name.parse::<LevelCode>() {
+            return generate(&code);
+        }
+        Level::load_path(&levels_dir().join(format!("{}.ron", name)))
+    }
+
+    pub fn load_path(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        ron::de::from_str(&contents)
+            .map_err(|e| format!("Could not parse {}: {}", path.display(), e))
+    }
+}
+
+fn levels_dir() -> PathBuf {
+    FileAssetIo::get_root_path().join("assets").join("levels")
+}
+
+pub fn level_names() -> Vec<String> {
+    /*
+     * The names of all level files, sorted
+     */
+    let mut names: Vec<String> = fs::read_dir(levels_dir())
+        .map(|entries| entries
+             .filter_map(|entry| entry.ok())
+             .map(|entry| entry.path())
+             .filter(|path| path.extension().map_or(false, |ext| ext == "ron"))
+             .filter_map(|path| path.file_stem()
+                         .map(|stem| stem.to_string_lossy().into_owned()))
+             .collect())
+        .unwrap_or_default();
+    names.sort();
+    names
+}
+
+/*
+ * Systems
+ */
+pub fn start_level_load(
+    mut commands: Commands,
+    pool: Res<AsyncComputeTaskPool>,
+    assets: Res<GameAssets>,
+    current_level: Res<CurrentLevel>,
+    ) {
+    /*
+     * Reads or generates the current level in the background, as
+     * generating can take a while, and says so in the meantime
+     */
+    let name = current_level.name.clone();
+    let task = pool.spawn(async move { Level::load(&name) });
+    commands.insert_resource(LevelLoad{ task });
+
+    commands.spawn_bundle(TextBundle {
+        style: Style {
+            // center the text
+            margin: Rect::all(Val::Auto),
+            ..Default::default()
+        },
+        text: Text::with_section(
+            format!("Loading {}...", current_level.name),
+            TextStyle {
+                font: assets.font.clone(),
+                font_size: 32.0,
+                color: Color::rgb(0.9, 0.9, 0.9),
+            },
+            Default::default(),
+        ),
+        ..Default::default()
+    })
+    .insert(LevelLoadingText);
+}
+
+pub fn finish_level_load(
+    mut commands: Commands,
+    mut load: ResMut<LevelLoad>,
+    mut state: ResMut<State<AppState>>,
+    ) {
+    /*
+     * Starts the game on the level once it is there,
+     * or goes back to the menu if it could not be had
+     */
+    let result = match future::block_on(future::poll_once(&mut load.task)) {
+        Some(result) => result,
+        None => return,
+    };
+    commands.remove_resource::<LevelLoad>();
+    match result {
+        Ok(level) => {
+            commands.insert_resource(level);
+            state.set(AppState::InGame).unwrap();
+        }
+        Err(e) => {
+            error!("{}", e);
+            state.set(AppState::MainMenu).unwrap();
+        }
+    }
+}
+
+pub fn clear_loading_text(mut commands: Commands, text_query: Query<Entity, With<LevelLoadingText>>) {
+    for entity in text_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -1,7 +1,9 @@
 use bevy::prelude::*;
 
+use crate::loading::GameAssets;
 use super::player::*;
 use super::coords::*;
+use super::scheme::Scheme;
 use num::complex;
 #[allow(non_camel_case_types)]
 type c32 = complex::Complex32;
@@ -10,10 +12,18 @@ type c32 = complex::Complex32;
 pub struct MeasurementDevice;
 #[derive(Component)]
 pub struct MeasurementIndicator;
+// The magnitude bar of an indicator, colored after the scheme
+#[derive(Component)]
+pub struct DeviceBar;
+// The green arrow of an indicator, flashed when a measurement fails
+#[derive(Component)]
+pub struct DeviceArrow;
 
 pub fn spawn_measurement_device(
     commands: &mut Commands,
-    asset_server: &Res<AssetServer>,
+    assets: &GameAssets,
+    grid: &GridConfig,
+    scheme: Scheme,
     state: QState,
     ) -> Entity {
     /*
@@ -24,7 +34,7 @@ pub fn spawn_measurement_device(
     let children: Vec<Entity> = state.map
         .iter()
         .map(|(gp, factor)| spawn_measurement_indicator(
-            commands, asset_server, *gp, *factor))
+            commands, assets, grid, scheme, *gp, *factor))
         .collect();
 
     // Spawn measurement device entity
@@ -43,19 +53,27 @@ pub fn spawn_measurement_device(
 }
 pub fn spawn_measurement_indicator(
     commands: &mut Commands,
-    asset_server: &Res<AssetServer>,
+    assets: &GameAssets,
+    grid: &GridConfig,
+    scheme: Scheme,
     gp: GridPos,
     factor: c32
     ) -> Entity {
 
-    // bar = 5 x 24
+    // bar = 5 x 24 on 64 pixel tiles
     // Position in world coordinates
-    let world_pos = grid_to_world_coordinates(&gp);
+    let world_pos = grid.grid_to_world(&gp);
+    let half_tile = grid.tile_size / 2.;
     // Barlength
-    let bar_length = (factor.norm() * 24.).ceil();
+    let bar_length = (factor.norm() * grid.px(24.)).ceil();
+    let bar_height = grid.px(5.);
 
     commands.spawn_bundle(SpriteBundle {
-        texture: asset_server.load("sprites/measuring_device.png"),
+        texture: assets.measuring_device.clone(),
+        sprite: Sprite{
+            custom_size: Some(grid.tile()),
+            ..Default::default()
+        },
         transform: Transform::from_xyz(world_pos.x, world_pos.y, 1.),
         ..Default::default()
     })
@@ -66,25 +84,30 @@ pub fn spawn_measurement_indicator(
         // Spawn bar
         parent.spawn_bundle(SpriteBundle{
                 sprite: Sprite {
-                    color: Color::rgb(0.2, 0.87, 0.08),
-                    custom_size: Some(Vec2::new(bar_length, 5.)),
+                    color: scheme.device_bar(),
+                    custom_size: Some(Vec2::new(bar_length, bar_height)),
                     ..Default::default()
                 },
-                // -32 because it starts from the middle of the tile
+                // - half_tile because it starts from the middle of the tile
                 // + bar_length/2 because the anchor is in the middle of the bar
-                // + 10 because the bar should be 9 pixels left of the boundry
-                transform: Transform::from_xyz(bar_length/2. - 32. + 10., 
-                                               -5./2. + 32. - 21., 1.),
+                // + 10 because the bar should be 10 pixels right of the boundary
+                transform: Transform::from_xyz(bar_length/2. - half_tile + grid.px(10.),
+                                               -bar_height/2. + half_tile - grid.px(21.), 1.),
                 ..Default::default()
-        });
-        //.insert(MagnitudeIndicator);
+        })
+        .insert(DeviceBar);
         // Spawn arrow
         parent.spawn_bundle(SpriteBundle{
-                texture: asset_server.load("sprites/green_arrow.png"),
-                transform: Transform::from_xyz(-14.+3.5, 18., 2.)
+                texture: assets.green_arrow.clone(),
+                sprite: Sprite {
+                    custom_size: Some(grid.art(7., 10.)),
+                    ..Default::default()
+                },
+                transform: Transform::from_xyz(grid.px(-14.+3.5), grid.px(18.), 2.)
                     .with_rotation(Quat::from_rotation_z(factor.arg())),
                 ..Default::default()
-        });
+        })
+        .insert(DeviceArrow);
         //.insert(PhaseIndicator);
     }).id()
 }
@@ -0,0 +1,425 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::fmt;
+use std::str::FromStr;
+use num::complex;
+use serde::{Serialize, Serializer, Deserialize, Deserializer};
+use serde::de::{self, MapAccess, Visitor};
+
+use super::coords::*;
+use super::player::QState;
+#[allow(non_camel_case_types)]
+type c32 = complex::Complex32;
+
+/*
+ * Printing
+ */
+impl fmt::Display for QState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        /*
+         * Writes the state in Dirac notation sorted by position,
+         * e.g. 0.707|1,0⟩ - 0.707i|2,0⟩. The precision (3 by default)
+         * is the most decimals written.
+         */
+        let precision = f.precision().unwrap_or(3);
+        let mut terms: Vec<_> = self.map.iter().collect();
+        terms.sort_by_key(|(gp, _)| **gp);
+        if terms.is_empty() {
+            return write!(f, "0");
+        }
+        for (i, (gp, factor)) in terms.iter().enumerate() {
+            let (negative, coefficient) = coefficient(**factor, precision);
+            match (i, negative) {
+                (0, false) => (),
+                (0, true) => write!(f, "-")?,
+                (_, false) => write!(f, " + ")?,
+                (_, true) => write!(f, " - ")?,
+            }
+            write!(f, "{}|{},{}⟩", coefficient, gp.x(), gp.y())?;
+        }
+        Ok(())
+    }
+}
+
+fn number(value: f32, precision: usize) -> String {
+    let text = format!("{:.*}", precision, value);
+    if text.contains('.') {
+        text.trim_end_matches('0').trim_end_matches('.').to_string()
+    } else {
+        text
+    }
+}
+
+fn coefficient(factor: c32, precision: usize) -> (bool, String) {
+    /*
+     * Whether the coefficient of a term is written with a minus,
+     * and the coefficient without it. Coefficients of 1 are left out.
+     */
+    let magnitude = |value: f32| number(value.abs(), precision);
+    let unit = |digits: String, suffix: &str| if digits == "1" {
+        suffix.to_string()
+    } else {
+        format!("{}{}", digits, suffix)
+    };
+    match (magnitude(factor.re) == "0", magnitude(factor.im) == "0") {
+        (_, true) => (factor.re < 0., unit(magnitude(factor.re), "")),
+        (true, false) => (factor.im < 0., unit(magnitude(factor.im), "i")),
+        (false, false) => (false, format!(
+            "({}{}{}i)",
+            number(factor.re, precision),
+            if factor.im < 0. { "-" } else { "+" },
+            magnitude(factor.im))),
+    }
+}
+
+/*
+ * Parsing
+ */
+impl FromStr for QState {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        /*
+         * Reads a state written as a sum of kets |x,y⟩ (or |x,y>), each
+         * with an optional coefficient. Coefficients are products of numbers,
+         * i, pi, sqrt, e^{...} and bracketed sums, e.g. 1/sqrt2 e^{i pi/4},
+         * and may multiply a bracketed state. Kets written more than once add up.
+         */
+        if s.trim() == "0" {
+            return Ok(QState{ map: HashMap::new() });
+        }
+        let mut parser = Parser{ chars: s.chars().collect(), pos: 0 };
+        parser.state().map_err(|e| format!("{}: {}", s, e))
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&mut self) -> Option<char> {
+        while self.chars.get(self.pos).map_or(false, |c| c.is_whitespace()) {
+            self.pos += 1;
+        }
+        self.chars.get(self.pos).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(c) {
+            self.pos += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn eat_word(&mut self, word: &str) -> bool {
+        self.peek();
+        let end = self.pos + word.chars().count();
+        if end <= self.chars.len() && self.chars[self.pos..end].iter().copied().eq(word.chars()) {
+            self.pos = end;
+            true
+        } else {
+            false
+        }
+    }
+
+    fn expected(&mut self, what: &str) -> String {
+        match self.peek() {
+            Some(c) => format!("expected {} at column {}, found {}", what, self.pos + 1, c),
+            None => format!("expected {} at the end", what),
+        }
+    }
+
+    fn group_ahead(&mut self) -> bool {
+        /*
+         * Whether a bracket comes next that holds kets,
+         * like in 1/sqrt2 (|1,0⟩ + |2,0⟩), rather than a number
+         */
+        if self.peek() != Some('(') {
+            return false;
+        }
+        let mut depth = 0;
+        for c in self.chars[self.pos..].iter() {
+            match c {
+                '(' => depth += 1,
+                ')' if depth == 1 => return false,
+                ')' => depth -= 1,
+                '|' => return true,
+                _ => (),
+            }
+        }
+        false
+    }
+
+    fn state(&mut self) -> Result<QState, String> {
+        let map = self.terms()?;
+        if self.peek().is_some() {
+            return Err(self.expected("+, - or the end"));
+        }
+        Ok(QState{ map })
+    }
+
+    fn terms(&mut self) -> Result<HashMap<GridPos, c32>, String> {
+        let mut map: HashMap<GridPos, c32> = HashMap::new();
+        let mut sign = if self.eat('-') { -1. } else { self.eat('+'); 1. };
+        loop {
+            let factor = if self.peek() == Some('|') || self.group_ahead() {
+                c32::new(sign, 0.)
+            } else {
+                self.product()? * sign
+            };
+            if self.group_ahead() {
+                self.eat('(');
+                for (gp, inner) in self.terms()? {
+                    *map.entry(gp).or_default() += factor * inner;
+                }
+                if !self.eat(')') {
+                    return Err(self.expected(")"));
+                }
+            } else {
+                *map.entry(self.ket()?).or_default() += factor;
+            }
+            sign = if self.eat('+') {
+                1.
+            } else if self.eat('-') {
+                -1.
+            } else {
+                break;
+            };
+        }
+        Ok(map)
+    }
+
+    fn ket(&mut self) -> Result<GridPos, String> {
+        if !self.eat('|') {
+            return Err(self.expected("a ket |x,y⟩"));
+        }
+        let x = self.integer()?;
+        if !self.eat(',') {
+            return Err(self.expected(","));
+        }
+        let y = self.integer()?;
+        if !self.eat('⟩') && !self.eat('>') {
+            return Err(self.expected("⟩ or >"));
+        }
+        Ok(GridPos::new(x, y))
+    }
+
+    fn integer(&mut self) -> Result<i32, String> {
+        self.peek();
+        let start = self.pos;
+        if self.chars.get(self.pos) == Some(&'-') {
+            self.pos += 1;
+        }
+        while self.chars.get(self.pos).map_or(false, |c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        let text: String = self.chars[start..self.pos].iter().collect();
+        text.parse().map_err(|_| {
+            self.pos = start;
+            self.expected("a coordinate")
+        })
+    }
+
+    fn sum(&mut self) -> Result<c32, String> {
+        let mut value = if self.eat('-') { -self.product()? } else { self.eat('+'); self.product()? };
+        loop {
+            if self.eat('+') {
+                value += self.product()?;
+            } else if self.eat('-') {
+                value -= self.product()?;
+            } else {
+                return Ok(value);
+            }
+        }
+    }
+
+    fn product(&mut self) -> Result<c32, String> {
+        // Factors next to each other are multiplied too
+        let mut value = self.factor()?;
+        loop {
+            match self.peek() {
+                Some('*') => {
+                    self.pos += 1;
+                    value *= self.factor()?;
+                }
+                Some('/') => {
+                    self.pos += 1;
+                    value /= self.factor()?;
+                }
+                Some(c) if !"|+-)}".contains(c) && !self.group_ahead() => value *= self.factor()?,
+                _ => return Ok(value),
+            }
+        }
+    }
+
+    fn factor(&mut self) -> Result<c32, String> {
+        match self.peek() {
+            Some(c) if c.is_ascii_digit() || c == '.' => {
+                let start = self.pos;
+                while self.chars.get(self.pos).map_or(false, |c| c.is_ascii_digit() || *c == '.') {
+                    self.pos += 1;
+                }
+                let text: String = self.chars[start..self.pos].iter().collect();
+                let value: f32 = text.parse().map_err(|_| {
+                    self.pos = start;
+                    self.expected("a number")
+                })?;
+                // A number written right before i is imaginary
+                if self.chars.get(self.pos) == Some(&'i') {
+                    self.pos += 1;
+                    Ok(c32::new(0., value))
+                } else {
+                    Ok(c32::new(value, 0.))
+                }
+            }
+            _ if self.eat_word("sqrt") || self.eat('√') => Ok(self.factor()?.sqrt()),
+            _ if self.eat_word("pi") || self.eat('π') => Ok(c32::new(PI, 0.)),
+            _ if self.eat_word("e^") => {
+                let exponent = if self.eat('{') {
+                    let exponent = self.sum()?;
+                    if !self.eat('}') {
+                        return Err(self.expected("}"));
+                    }
+                    exponent
+                } else {
+                    self.factor()?
+                };
+                Ok(exponent.exp())
+            }
+            _ if self.eat('i') => Ok(c32::new(0., 1.)),
+            _ if self.eat('(') => {
+                let value = self.sum()?;
+                if !self.eat(')') {
+                    return Err(self.expected(")"));
+                }
+                Ok(value)
+            }
+            _ => Err(self.expected("a number")),
+        }
+    }
+}
+
+/*
+ * Serialization
+ */
+// States are saved as maps from positions to factors, which is exact,
+// and can be read from those or from Dirac notation
+impl Serialize for QState {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.map.serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for QState {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(QStateVisitor)
+    }
+}
+
+struct QStateVisitor;
+
+impl<'de> Visitor<'de> for QStateVisitor {
+    type Value = QState;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "a state in Dirac notation or a map from positions to factors")
+    }
+
+    fn visit_str<E: de::Error>(self, v: &str) -> Result<QState, E> {
+        v.parse().map_err(E::custom)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut access: A) -> Result<QState, A::Error> {
+        let mut map = HashMap::new();
+        while let Some((gp, factor)) = access.next_entry::<GridPos, c32>()? {
+            map.insert(gp, factor);
+        }
+        Ok(QState{ map })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::f32::consts::FRAC_1_SQRT_2;
+    use crate::game::level::DeviceSpec;
+
+    fn state(terms: &[((i32, i32), c32)]) -> QState {
+        QState{ map: terms.iter().map(|&((x, y), factor)| (GridPos::new(x, y), factor)).collect() }
+    }
+
+    fn assert_near(a: &QState, b: &QState) {
+        let positions = a.map.keys().chain(b.map.keys());
+        for gp in positions {
+            let factor = |s: &QState| s.map.get(gp).copied().unwrap_or_default();
+            assert!((factor(a) - factor(b)).norm() < 1e-3, "{} is not {}", a, b);
+        }
+    }
+
+    #[test]
+    fn print_then_parse() {
+        let states = [
+            state(&[]),
+            state(&[((0, 0), c32::new(1., 0.))]),
+            state(&[((1, 0), c32::new(FRAC_1_SQRT_2, 0.)), ((2, 0), c32::new(0., -FRAC_1_SQRT_2))]),
+            state(&[((-3, 2), c32::new(0.6, 0.8)), ((4, -1), c32::new(-0.25, 0.))]),
+        ];
+        for s in states.iter() {
+            let parsed: QState = s.to_string().parse().unwrap();
+            assert_near(&parsed, s);
+        }
+    }
+
+    #[test]
+    fn parse_examples() {
+        let half = c32::new(FRAC_1_SQRT_2, 0.);
+        let examples = [
+            ("0.707|1,0⟩ + 0.707i|2,0⟩", state(&[((1, 0), half), ((2, 0), c32::i() * half)])),
+            ("0.707|1,0> + 0.707i|2,0>", state(&[((1, 0), half), ((2, 0), c32::i() * half)])),
+            ("1/sqrt2 |0,0> - 1/√2 |1,0>", state(&[((0, 0), half), ((1, 0), -half)])),
+            ("1/sqrt2 (|1,0> + |2,0>)", state(&[((1, 0), half), ((2, 0), half)])),
+            ("i|2,0>", state(&[((2, 0), c32::i())])),
+            ("e^{i pi/4}|0,0>", state(&[((0, 0), c32::from_polar(1., PI / 4.))])),
+        ];
+        for (text, expected) in examples.iter() {
+            let parsed: QState = text.parse().unwrap_or_else(|e| panic!("{}: {}", text, e));
+            assert_near(&parsed, expected);
+        }
+    }
+
+    #[test]
+    fn reject_malformed() {
+        for text in ["|1,0", "e^{", "1/", "|1,0> +", "2|x,0>"] {
+            assert!(text.parse::<QState>().is_err(), "{} was accepted", text);
+        }
+    }
+
+    #[test]
+    fn deserialize_map_and_string() {
+        let half = c32::new(FRAC_1_SQRT_2, 0.);
+        let expected = state(&[((1, 0), half), ((2, 0), half)]);
+
+        let from_map: DeviceSpec = ron::de::from_str(
+            "(state: {(x: 1, y: 0): (0.70710677, 0), (x: 2, y: 0): (0.70710677, 0)}, door: None)"
+        ).unwrap();
+        assert_near(&from_map.state, &expected);
+
+        let from_string: DeviceSpec = ron::de::from_str(
+            "(state: \"1/sqrt2 (|1,0> + |2,0>)\", door: Some((x: 3, y: 0)))"
+        ).unwrap();
+        assert_near(&from_string.state, &expected);
+        assert_eq!(from_string.door, Some(GridPos::new(3, 0)));
+    }
+
+    #[test]
+    fn serialize_then_deserialize() {
+        let s = state(&[((0, 1), c32::new(0.6, 0.)), ((-2, 5), c32::new(0., -0.8))]);
+        let text = ron::ser::to_string(&s).unwrap();
+        let read: QState = ron::de::from_str(&text).unwrap();
+        assert_eq!(read.map, s.map);
+    }
+}
@@ -1,267 +1,294 @@
-use rand;
 use bevy::prelude::*;
-use bevy_ecs_tilemap::prelude::*;
-use num::complex;
+use bevy::ecs::system::SystemParam;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use crate::actions::Action;
 use super::player::*;
 use super::coords::*;
 use super::measurer::*;
-
-#[allow(non_camel_case_types)]
-type c32 = complex::Complex32;
+use super::selection::{Selection, ClearSelectionEvent};
+use super::level::{Gate, GateInventory};
+use super::event_log::{OperationLog, Outcome};
+use super::replay::Playback;
+use super::animation::GateAnimation;
 
 /*
  * Components
  */
 #[derive(Component)]
 pub struct Blocking;
-#[derive(Component)]
-pub struct SelectedPos;
 
 /*
  * Events
  */
 pub struct SwitchEvent{
-    gp1: GridPos,
-    gp2: GridPos,
+    pub gp1: GridPos,
+    pub gp2: GridPos,
 }
 pub struct MixEvent{
-    gp1: GridPos,
-    gp2: GridPos,
+    pub gp1: GridPos,
+    pub gp2: GridPos,
+}
+// The k-tile gates act on the tiles in the order they were selected
+pub struct CycleEvent{
+    pub tiles: Vec<GridPos>,
+}
+pub struct FourierEvent{
+    pub tiles: Vec<GridPos>,
+}
+pub struct DiffusionEvent{
+    pub tiles: Vec<GridPos>,
 }
 pub struct MeasureEvent{
-    entity: Entity,
+    pub entity: Entity,
 }
 pub struct MeasureSuccessEvent{
     pub entity: Entity,
 }
+pub struct MeasureFailureEvent{
+    pub entity: Entity,
+}
+pub struct UndoEvent;
+
+// Writers for every operation, for systems that can send any of them
+#[derive(SystemParam)]
+pub struct OperationWriters<'w, 's> {
+    pub switch: EventWriter<'w, 's, SwitchEvent>,
+    pub mix: EventWriter<'w, 's, MixEvent>,
+    pub cycle: EventWriter<'w, 's, CycleEvent>,
+    pub fourier: EventWriter<'w, 's, FourierEvent>,
+    pub diffusion: EventWriter<'w, 's, DiffusionEvent>,
+    pub measure: EventWriter<'w, 's, MeasureEvent>,
+    pub undo: EventWriter<'w, 's, UndoEvent>,
+}
+
+impl<'w, 's> OperationWriters<'w, 's> {
+    pub fn send_gate(&mut self, gate: Gate, tiles: Vec<GridPos>) -> Result<(), String> {
+        /*
+         * Sends the event of a gate acting on the tiles, if it can act on them.
+         * Measurements act on devices rather than tiles, so they are left out.
+         */
+        gate.check_tiles(&tiles)?;
+        match gate {
+            Gate::Switch => self.switch.send(SwitchEvent{ gp1: tiles[0], gp2: tiles[1] }),
+            Gate::Mix => self.mix.send(MixEvent{ gp1: tiles[0], gp2: tiles[1] }),
+            Gate::Cycle => self.cycle.send(CycleEvent{ tiles }),
+            Gate::Fourier => self.fourier.send(FourierEvent{ tiles }),
+            Gate::Diffusion => self.diffusion.send(DiffusionEvent{ tiles }),
+            Gate::Measure => (),
+        }
+        Ok(())
+    }
+}
+
+/*
+ * Resources
+ */
+// Decides the outcomes of measurements. It is seeded, so that
+// the same seed and operations always play out the same way.
+pub struct GameRng {
+    pub seed: u64,
+    pub rng: StdRng,
+}
+
+impl GameRng {
+    pub fn new(seed: u64) -> Self {
+        GameRng{ seed, rng: StdRng::seed_from_u64(seed) }
+    }
+}
+
+impl Default for GameRng {
+    fn default() -> Self {
+        GameRng::new(rand::random())
+    }
+}
 
-pub struct ClearSelectionEvent;
+#[derive(Default)]
+pub struct UndoHistory {
+    // Player states from before each undoable operation
+    // and the gate used in it, latest last
+    steps: Vec<(QState, Gate)>,
+}
 
 /*
 * Systems
 */
 pub fn switcher(mut switche_reader: EventReader<SwitchEvent>,
     mut player_state_query: Query<&mut QState, With<Player>>,
+    mut history: ResMut<UndoHistory>,
+    mut log: ResMut<OperationLog>,
     ) {
-    // The without superposition is so that bevy knows that the queries 
-    // are disjoint since we access the first one mutably
-
     for switch_event in switche_reader.iter() {
         // Switch the superpositions
         let mut state = player_state_query.single_mut();
-
-        let a_i = *state.map.get(&switch_event.gp1)
-            .unwrap_or(&c32::new(0., 0.));
-        let b_i = *state.map.get(&switch_event.gp2)
-            .unwrap_or(&c32::new(0., 0.));
-        let a_f = b_i;
-        let b_f = a_i;
-
-        if a_f == c32::new(0., 0.) {
-            // Removes value if there, does nothing if not
-            state.map.remove(&switch_event.gp1);
-        } else {
-            // Replaces value if already there and creates new if not
-            state.map.insert(switch_event.gp1, a_f);
-        }
-        if b_f == c32::new(0., 0.) {
-            // Removes value if there, does nothing if not
-            state.map.remove(&switch_event.gp2);
-        } else {
-            // Replaces value if already there and creates new if not
-            state.map.insert(switch_event.gp2, b_f);
-        }
+        history.steps.push((state.clone(), Gate::Switch));
+        state.switch(switch_event.gp1, switch_event.gp2);
+        let tiles = [switch_event.gp1, switch_event.gp2];
+        log.push_applied(Gate::Switch, &tiles, &state);
     }
 }
 
 pub fn mixer(
     mut mixe_reader: EventReader<MixEvent>,
     mut player_state_query: Query<&mut QState, With<Player>>,
+    mut history: ResMut<UndoHistory>,
+    mut log: ResMut<OperationLog>,
     ) {
     for mix_event in mixe_reader.iter() {
 
         let mut state = player_state_query.single_mut();
+        history.steps.push((state.clone(), Gate::Mix));
+        state.mix(mix_event.gp1, mix_event.gp2);
+        let tiles = [mix_event.gp1, mix_event.gp2];
+        log.push_applied(Gate::Mix, &tiles, &state);
+    }
+}
 
-        let a_i = *state.map.get(&mix_event.gp1)
-            .unwrap_or(&c32::new(0., 0.));
-        let b_i = *state.map.get(&mix_event.gp2)
-            .unwrap_or(&c32::new(0., 0.));
-        let a_f = (a_i - b_i)/2_f32.sqrt();
-        let b_f = (a_i + b_i)/2_f32.sqrt();
-        println!("a_f: {}, b_f: {}", a_f, b_f);
+pub fn cycler(
+    mut cycle_reader: EventReader<CycleEvent>,
+    mut player_state_query: Query<&mut QState, With<Player>>,
+    mut history: ResMut<UndoHistory>,
+    mut log: ResMut<OperationLog>,
+    ) {
+    for cycle_event in cycle_reader.iter() {
+        let mut state = player_state_query.single_mut();
+        history.steps.push((state.clone(), Gate::Cycle));
+        state.cycle(&cycle_event.tiles);
+        log.push_applied(Gate::Cycle, &cycle_event.tiles, &state);
+    }
+}
 
-        if a_f == c32::new(0., 0.) {
-            // Removes value if there, does nothing if not
-            state.map.remove(&mix_event.gp1);
-        } else {
-            // Replaces value if already there and creates new if not
-            state.map.insert(mix_event.gp1, a_f);
-        }
-        if b_f == c32::new(0., 0.) {
-            // Removes value if there, does nothing if not
-            state.map.remove(&mix_event.gp2);
-        } else {
-            // Replaces value if already there and creates new if not
-            state.map.insert(mix_event.gp2, b_f);
-        }
+pub fn fourier_transformer(
+    mut fourier_reader: EventReader<FourierEvent>,
+    mut player_state_query: Query<&mut QState, With<Player>>,
+    mut history: ResMut<UndoHistory>,
+    mut log: ResMut<OperationLog>,
+    ) {
+    for fourier_event in fourier_reader.iter() {
+        let mut state = player_state_query.single_mut();
+        history.steps.push((state.clone(), Gate::Fourier));
+        state.fourier(&fourier_event.tiles);
+        log.push_applied(Gate::Fourier, &fourier_event.tiles, &state);
     }
 }
 
+pub fn diffuser(
+    mut diffusion_reader: EventReader<DiffusionEvent>,
+    mut player_state_query: Query<&mut QState, With<Player>>,
+    mut history: ResMut<UndoHistory>,
+    mut log: ResMut<OperationLog>,
+    ) {
+    for diffusion_event in diffusion_reader.iter() {
+        let mut state = player_state_query.single_mut();
+        history.steps.push((state.clone(), Gate::Diffusion));
+        state.diffusion(&diffusion_event.tiles);
+        log.push_applied(Gate::Diffusion, &diffusion_event.tiles, &state);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
 pub fn measure(
     mut measurement_event_reader: EventReader<MeasureEvent>,
     mut success_event_writer: EventWriter<MeasureSuccessEvent>,
+    mut failure_event_writer: EventWriter<MeasureFailureEvent>,
     measurement_state_query: Query<&QState, With<MeasurementDevice>>,
     mut player_state_query: Query<&mut QState, (With<Player>, Without<MeasurementDevice>)>,
+    mut history: ResMut<UndoHistory>,
+    mut log: ResMut<OperationLog>,
+    mut rng: ResMut<GameRng>,
     ) {
 
     for meas_event in measurement_event_reader.iter() {
+        // Measurements are irreversible, so nothing before them can be undone
+        history.steps.clear();
+
         let success_state = measurement_state_query.get(meas_event.entity)
             .unwrap();
         let mut player_state = player_state_query.single_mut();
-        let scal_prod = player_state.scal_prod(success_state);
-        println!("Prob of success = {}", scal_prod.norm_sqr());
-        if rand::random::<f32>() < scal_prod.norm_sqr() {
-            *player_state = (*success_state).clone() * scal_prod.conj() / scal_prod.norm();
+        let prob = player_state.scal_prod(success_state).norm_sqr();
+        let mut tiles: Vec<GridPos> = success_state.map.keys().copied().collect();
+        tiles.sort();
+        if rng.rng.gen::<f32>() < prob {
+            *player_state = player_state.collapse_onto(success_state);
             success_event_writer.send(MeasureSuccessEvent{ entity: meas_event.entity });
+            log.push(Gate::Measure, &tiles, Outcome::Succeeded, prob, player_state.factors(&tiles));
         } else {
-            *player_state = ((*player_state).clone()
-                - scal_prod.conj() * (*success_state).clone() )
-                / (1. - scal_prod.norm_sqr()).sqrt()
+            *player_state = player_state.collapse_away_from(success_state);
+            failure_event_writer.send(MeasureFailureEvent{ entity: meas_event.entity });
+            log.push(Gate::Measure, &tiles, Outcome::Failed, 1. - prob, player_state.factors(&tiles));
         }
     }
 }
 
-pub fn select_positions(mut commands: Commands,
-    windows: Res<Windows>,
-    asset_server: Res<AssetServer>,
-    mouse_button_input: Res<Input<MouseButton>>,
-    selected_tiles: Query<(Entity, &GridPos), With<SelectedPos>>,
-    tile_query: Query<&TilePos, (With<Tile>, Without<Blocking>)>,
-    blocking_query: Query<&GridPos, With<Blocking>>,
-    camera_query: Query<(&Transform, &OrthographicProjection)>,
-    ) {
-
-    if mouse_button_input.just_released(MouseButton::Left) {
-    
-        // get the primary window
-        let wnd = windows.get_primary().unwrap();
-
-        // check if the cursor is in the primary window
-        let world_pos = if let Some(screen_pos) = wnd.cursor_position() {
-
-            // get the size of the window
-            let size = Vec2::new(wnd.width() as f32, wnd.height() as f32);
-
-            // assuming there is exactly one main camera entity, so this is OK
-            let (camera_transform, ortho_proj) = camera_query.single();
-
-            // Screen coordinates are from bottom left position of screen
-            // But the default orthographic projection camera starts with (0,0)
-            // at the center of the screen. So subtract half of the screen size
-            let mut p = screen_pos - size / 2.0;
-
-            // The camera may also have a different scale meaning that 
-            // one screen pixel is larger / smaller than one world pixel
-            // as seen by the camera.
-            p *= ortho_proj.scale;
-
-            // And finaly, the camera may have shifted away from pointing at (0,0)
-            // So apply the same transformation that the camera has done to the
-            // computed value
-            camera_transform.compute_matrix() * p.extend(0.0).extend(1.0)
-        } else {return;};
-
-        let grid_pos = world_to_grid_coordinates(&Vec2::new(world_pos.x, world_pos.y));
-        // Check that there is a tile there that is selectable
-        // Otherwise the square cannot be selected.
-        let mut found_selectable_tile = false;
-        for tp in tile_query.iter() {
-            if *tp == grid_pos {
-                // There is a non-blocking tile on the position clicked
-                found_selectable_tile = true;
-            }
-        }
-        for gp in blocking_query.iter() {
-            if *gp == grid_pos {
-                // There is some blocking element on top of the tile
-                found_selectable_tile = false;
-            }
-        }
-        if !found_selectable_tile { return; }
-
-        // If there are previously selected squares,
-        // the newly selected square must be a neighbour of one of them
-        // and cannot already be selected.
-        if !selected_tiles.is_empty() {
-            // Make true if tile is a neighbour of some selected tile
-            let mut tile_is_neighbour = false;
-            for (entity, selected_tile_gridpos) in selected_tiles.iter() {
-                // Tile is already selected
-                // TODO: Deselect it in a better way, now the player could
-                // select 1, 2, 3, and the deselct 2 and the selection would no
-                // longer be only neighbours.
-                if grid_pos == *selected_tile_gridpos {
-                    println!("Despawning");
-                    commands.entity(entity).despawn();
-                    return;
-                }
-                if are_neighbours(&grid_pos, selected_tile_gridpos) {
-                    tile_is_neighbour = true;
-                }
-            }
-            if !tile_is_neighbour {
-                return;
-            }
-        }
-        // You cannot have more than two squares selected at once
-        if selected_tiles.iter().count() == 2 {
-            return;
-        }
-
-        let world_pos_corner = grid_to_world_coordinates(&grid_pos);
-        commands.spawn_bundle(SpriteBundle {
-                texture: asset_server.load("sprites/select.png"),
-                transform: Transform::from_xyz(world_pos_corner.x,
-                                               world_pos_corner.y,
-                                               20.),
-                ..Default::default()
-            })
-            .insert(SelectedPos)
-            .insert(grid_pos);
-
-
-    }
-}
-pub fn action_system(keys: Res<Input<KeyCode>>,
-    selected_tiles: Query<&GridPos, With<SelectedPos>>,
+#[allow(clippy::too_many_arguments)]
+pub fn action_system(actions: Res<Input<Action>>,
+    playback: Option<Res<Playback>>,
+    animation: Res<GateAnimation>,
+    selection: Res<Selection>,
+    mut inventory: ResMut<GateInventory>,
     measurement_devices: Query<(Entity, &QState), With<MeasurementDevice>>,
     mut switche_writer: EventWriter<SwitchEvent>,
     mut mixe_writer: EventWriter<MixEvent>,
+    mut cycle_writer: EventWriter<CycleEvent>,
+    mut fourier_writer: EventWriter<FourierEvent>,
+    mut diffusion_writer: EventWriter<DiffusionEvent>,
     mut mease_writer: EventWriter<MeasureEvent>,
+    mut undo_writer: EventWriter<UndoEvent>,
     mut clear_selection_event_writer: EventWriter<ClearSelectionEvent>
     ) {
-    if keys.just_pressed(KeyCode::P) {
+    /*
+     * Sends the operation asked for if the selection fits it and the level
+     * has a use of the gate left. Otherwise nothing happens.
+     * A replay being played back does the operations instead,
+     * and nothing is done while a gate is animated.
+     */
+    if playback.is_some() || animation.running() {
+        return;
+    }
+    if actions.just_pressed(Action::Undo) {
+        undo_writer.send(UndoEvent);
+    }
+    if actions.just_pressed(Action::Switch) {
         // Check that only two tiles are selected
-        if selected_tiles.iter().count() != 2 {
-            return;
+        if let [gp1, gp2] = selection.tiles[..] {
+            if inventory.try_use(Gate::Switch) {
+                switche_writer.send(SwitchEvent{ gp1, gp2 });
+                clear_selection_event_writer.send(ClearSelectionEvent);
+            }
         }
-        let mut it = selected_tiles.iter();
-        switche_writer.send(SwitchEvent{ gp1: *it.next().unwrap(), gp2: *it.next().unwrap() });
-        clear_selection_event_writer.send(ClearSelectionEvent);
     }
-    if keys.just_pressed(KeyCode::O) {
+    if actions.just_pressed(Action::Mix) {
         // Check that only two tiles are selected
-        if selected_tiles.iter().count() != 2 {
-            return;
+        if let [gp1, gp2] = selection.tiles[..] {
+            if inventory.try_use(Gate::Mix) {
+                mixe_writer.send(MixEvent{ gp1, gp2 });
+                clear_selection_event_writer.send(ClearSelectionEvent);
+            }
         }
-        let mut it = selected_tiles.iter();
-        mixe_writer.send(MixEvent{ gp1: *it.next().unwrap(), gp2: *it.next().unwrap() });
+    }
+    // The k-tile gates need at least two tiles to do anything
+    if actions.just_pressed(Action::Cycle) && selection.tiles.len() >= 2
+        && inventory.try_use(Gate::Cycle) {
+        cycle_writer.send(CycleEvent{ tiles: selection.tiles.clone() });
+        clear_selection_event_writer.send(ClearSelectionEvent);
+    }
+    if actions.just_pressed(Action::Fourier) && selection.tiles.len() >= 2
+        && inventory.try_use(Gate::Fourier) {
+        fourier_writer.send(FourierEvent{ tiles: selection.tiles.clone() });
         clear_selection_event_writer.send(ClearSelectionEvent);
     }
-    if keys.just_pressed(KeyCode::I) {
-        if let Ok(gp) = selected_tiles.get_single() {
-            for (entity, state) in measurement_devices.iter() {
-                if state.map.contains_key(gp) {
+    if actions.just_pressed(Action::Diffusion) && selection.tiles.len() >= 2
+        && inventory.try_use(Gate::Diffusion) {
+        diffusion_writer.send(DiffusionEvent{ tiles: selection.tiles.clone() });
+        clear_selection_event_writer.send(ClearSelectionEvent);
+    }
+    if actions.just_pressed(Action::Measure) {
+        if let [gp] = selection.tiles[..] {
+            let device = measurement_devices.iter()
+                .find(|(_, state)| state.map.contains_key(&gp))
+                .map(|(entity, _)| entity);
+            if let Some(entity) = device {
+                if inventory.try_use(Gate::Measure) {
                     mease_writer.send(MeasureEvent{ entity });
                 }
             }
@@ -270,13 +297,23 @@ pub fn action_system(keys: Res<Input<KeyCode>>,
     }
 }
 
-pub fn clear_selection(mut commands: Commands,
-    selected_query: Query<Entity, With<SelectedPos>>,
-    mut clear_selection_ev: EventReader<ClearSelectionEvent>
+pub fn undo(mut undo_reader: EventReader<UndoEvent>,
+    mut history: ResMut<UndoHistory>,
+    mut inventory: ResMut<GateInventory>,
+    mut player_state_query: Query<&mut QState, With<Player>>,
+    mut log: ResMut<OperationLog>,
+    mut clear_selection_event_writer: EventWriter<ClearSelectionEvent>
     ) {
-    for _ in clear_selection_ev.iter() {
-        for entity in selected_query.iter() {
-            commands.entity(entity).despawn_recursive();
+    /*
+     * Restores the player state from before the last operation
+     * and gives back the gate use it cost
+     */
+    for _ in undo_reader.iter() {
+        if let Some((previous, gate)) = history.steps.pop() {
+            *player_state_query.single_mut() = previous;
+            inventory.refund(gate);
+            log.push(gate, &[], Outcome::Undone, 1., Vec::new());
+            clear_selection_event_writer.send(ClearSelectionEvent);
         }
     }
 }
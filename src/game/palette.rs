@@ -0,0 +1,79 @@
+use bevy::prelude::*;
+
+use crate::actions::{Binding, Bindings};
+use crate::loading::GameAssets;
+use super::level::{CurrentLevel, GateInventory};
+
+/*
+ * Components
+ */
+#[derive(Component)]
+pub struct GatePalette;
+
+pub fn spawn_gate_palette(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    ) {
+    /*
+     * Spawns the panel listing the available gates.
+     * Its text is filled in by update_gate_palette.
+     */
+    commands.spawn_bundle(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                left: Val::Px(10.0),
+                bottom: Val::Px(10.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: assets.font.clone(),
+                font_size: 24.0,
+                color: Color::rgb(0.9, 0.9, 0.9),
+            },
+            Default::default(),
+        ),
+        ..Default::default()
+    })
+    .insert(GatePalette);
+}
+
+pub fn update_gate_palette(
+    inventory: Res<GateInventory>,
+    bindings: Res<Bindings>,
+    current_level: Res<CurrentLevel>,
+    mut palette_query: Query<&mut Text, With<GatePalette>>,
+    ) {
+    /*
+     * Names the level, so that generated ones can be shared by their code,
+     * and lists its gates with their keys and remaining uses
+     */
+    if !inventory.is_changed() && !bindings.is_changed() && !current_level.is_changed() {
+        return;
+    }
+    let gates = inventory.uses.iter()
+        .map(|(gate, uses)| {
+            let keys = bindings.get(gate.action())
+                .iter()
+                .map(Binding::name)
+                .collect::<Vec<_>>()
+                .join("/");
+            let remaining = match uses {
+                None => "unlimited".to_string(),
+                Some(0) => "used up".to_string(),
+                Some(n) => format!("{} left", n),
+            };
+            format!("{} [{}]: {}", gate.name(), keys, remaining)
+        });
+    let lines: Vec<String> = std::iter::once(format!("Level {}", current_level.name))
+        .chain(gates)
+        .collect();
+
+    for mut text in palette_query.iter_mut() {
+        text.sections[0].value = lines.join("\n");
+    }
+}
@@ -0,0 +1,123 @@
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+
+use super::MainCamera;
+
+pub fn screen_to_world(
+    screen_pos: Vec2,
+    window_size: Vec2,
+    projection_matrix: Mat4,
+    camera_transform: &GlobalTransform,
+    ) -> Vec2 {
+    /*
+     * Converts a position in window coordinates (origin in the bottom left)
+     * to world coordinates as seen by a camera.
+     *
+     * Only the ratio screen_pos / window_size matters, so the two just have
+     * to be given in the same units. Bevy reports both the cursor position
+     * and the window size in logical pixels, which means that the scale
+     * factor of HiDPI screens cancels out.
+     */
+
+    // Normalized device coordinates go from -1 to 1 across the window
+    let ndc = (screen_pos / window_size) * 2.0 - Vec2::ONE;
+
+    // Undo the projection (which takes care of the window size and the
+    // zoom level of the camera) and then the camera transform
+    let ndc_to_world = camera_transform.compute_matrix() * projection_matrix.inverse();
+    ndc_to_world.project_point3(ndc.extend(0.0)).truncate()
+}
+
+pub fn cursor_world_position(
+    windows: &Windows,
+    camera_query: &Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    ) -> Option<Vec2> {
+    /*
+     * Returns the world position under the cursor, using whichever main
+     * camera renders to the window the cursor is in.
+     * Returns None if the cursor is not in any such window.
+     */
+    for (camera, camera_transform) in camera_query.iter() {
+        let window = match windows.get(camera.window) {
+            Some(window) => window,
+            None => continue,
+        };
+        if let Some(screen_pos) = window.cursor_position() {
+            let window_size = Vec2::new(window.width(), window.height());
+            return Some(screen_to_world(screen_pos,
+                                        window_size,
+                                        camera.projection_matrix,
+                                        camera_transform));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bevy::render::camera::{CameraProjection, OrthographicProjection};
+
+    fn projection(width: f32, height: f32, scale: f32) -> Mat4 {
+        // The projection of a 2D camera looking at a window of the given size
+        let mut projection = OrthographicProjection{ scale, ..Default::default() };
+        projection.update(width, height);
+        projection.get_projection_matrix()
+    }
+
+    fn assert_near(actual: Vec2, expected: Vec2) {
+        assert!((actual - expected).length() < 1e-3, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn window_center_and_corners() {
+        let window = Vec2::new(800., 600.);
+        let matrix = projection(window.x, window.y, 1.);
+        let camera = GlobalTransform::identity();
+
+        assert_near(screen_to_world(window / 2., window, matrix, &camera), Vec2::ZERO);
+        assert_near(screen_to_world(Vec2::ZERO, window, matrix, &camera), Vec2::new(-400., -300.));
+        assert_near(screen_to_world(window, window, matrix, &camera), Vec2::new(400., 300.));
+        assert_near(screen_to_world(Vec2::new(0., 600.), window, matrix, &camera),
+                    Vec2::new(-400., 300.));
+    }
+
+    #[test]
+    fn resized_window() {
+        let window = Vec2::new(1920., 1080.);
+        let matrix = projection(window.x, window.y, 1.);
+        let camera = GlobalTransform::identity();
+
+        assert_near(screen_to_world(window / 2., window, matrix, &camera), Vec2::ZERO);
+        assert_near(screen_to_world(window, window, matrix, &camera), Vec2::new(960., 540.));
+        assert_near(screen_to_world(Vec2::new(1060., 540.), window, matrix, &camera),
+                    Vec2::new(100., 0.));
+    }
+
+    #[test]
+    fn scale_factor_cancels_out() {
+        // A window of 640 x 480 logical pixels on a screen with a scale
+        // factor of 2, given in physical pixels instead
+        let logical = Vec2::new(640., 480.);
+        let matrix = projection(logical.x, logical.y, 1.);
+        let camera = GlobalTransform::identity();
+        let cursor = Vec2::new(100., 50.);
+
+        let from_logical = screen_to_world(cursor, logical, matrix, &camera);
+        let from_physical = screen_to_world(cursor * 2., logical * 2., matrix, &camera);
+        assert_near(from_logical, Vec2::new(-220., -190.));
+        assert_near(from_physical, from_logical);
+    }
+
+    #[test]
+    fn translated_and_zoomed_camera() {
+        let window = Vec2::new(800., 600.);
+        let matrix = projection(window.x, window.y, 2.);
+        let camera = GlobalTransform::from_xyz(100., -50., 999.);
+
+        assert_near(screen_to_world(window / 2., window, matrix, &camera), Vec2::new(100., -50.));
+        assert_near(screen_to_world(Vec2::ZERO, window, matrix, &camera), Vec2::new(-700., -650.));
+        assert_near(screen_to_world(Vec2::new(500., 300.), window, matrix, &camera),
+                    Vec2::new(300., -50.));
+    }
+}
@@ -1,12 +1,24 @@
 use std::collections::HashMap;
 use std::ops::{Add, Sub, Mul, Div};
 use bevy::prelude::*;
+use bevy::log::Level;
 use num::complex;
+use crate::loading::GameAssets;
 use super::coords::*;
+use super::level::Gate;
+use super::animation::DisplayedState;
 use super::measurer::MeasurementIndicator;
+use super::diagnostics::{Verbosity, RENDER_SYNC};
+use super::wave_overlay::WaveOverlay;
+use super::scheme::Scheme;
 #[allow(non_camel_case_types)]
 type c32 = complex::Complex32;
 
+// Amplitudes with a smaller norm squared than this are treated as zero,
+// so that rounding errors do not leave behind invisible superpositions
+const NEGLIGIBLE_PROB: f32 = 1e-10;
+
+// Printed, parsed and serialized in notation.rs
 #[derive(Component, Clone)]
 pub struct QState {
     pub map: HashMap<GridPos, c32>,
@@ -27,7 +39,7 @@ impl Add for QState {
 
     fn add(mut self, other: Self) -> Self {
         for (o_key, o_val) in other.map.iter() {
-            if let Some(s_val) = self.map.get_mut(&o_key) {
+            if let Some(s_val) = self.map.get_mut(o_key) {
                 *s_val += o_val;
             } else {
                 self.map.insert(*o_key, *o_val);
@@ -41,7 +53,7 @@ impl Sub for QState {
 
     fn sub(mut self, other: Self) -> Self {
         for (o_key, o_val) in other.map.iter() {
-            if let Some(s_val) = self.map.get_mut(&o_key) {
+            if let Some(s_val) = self.map.get_mut(o_key) {
                 *s_val -= o_val;
             } else {
                 self.map.insert(*o_key, -*o_val);
@@ -56,7 +68,7 @@ impl Mul<c32> for QState {
 
     fn mul(mut self, rhs: c32) -> Self {
         for val in self.map.values_mut() {
-            *val = *val * rhs;
+            *val *= rhs;
         }
         self
     }
@@ -66,7 +78,7 @@ impl Mul<QState> for c32 {
 
     fn mul(self, mut rhs: QState) -> QState {
         for val in rhs.map.values_mut() {
-            *val = *val * self;
+            *val *= self;
         }
         rhs
     }
@@ -77,7 +89,7 @@ impl Div<f32> for QState {
 
     fn div(mut self, rhs: f32) -> Self {
         for val in self.map.values_mut() {
-            *val = *val / rhs;
+            *val /= rhs;
         }
         self
     }
@@ -96,23 +108,155 @@ impl QState {
         }
         rv
     }
+
+    pub fn collapse_onto(&self, success_state: &Self) -> Self {
+        /*
+         * The state after a successful measurement, i.e. the success state
+         * with the phase of the projection onto it.
+         * Only makes sense if the success probability is nonzero.
+         */
+        let scal_prod = self.scal_prod(success_state);
+        (*success_state).clone() * scal_prod.conj() / scal_prod.norm()
+    }
+
+    pub fn collapse_away_from(&self, success_state: &Self) -> Self {
+        /*
+         * The state after a failed measurement, i.e. the normalized
+         * part of the state orthogonal to the success state.
+         * Only makes sense if the success probability is less than one.
+         */
+        let scal_prod = self.scal_prod(success_state);
+        let mut state = ((*self).clone() - scal_prod.conj() * (*success_state).clone())
+            / (1. - scal_prod.norm_sqr()).sqrt();
+        // Remove what the success state cancelled
+        state.map.retain(|_, factor| factor.norm_sqr() >= NEGLIGIBLE_PROB);
+        state
+    }
+
+    pub fn normalize(&mut self) {
+        let norm = self.map.values().map(|factor| factor.norm_sqr()).sum::<f32>().sqrt();
+        if norm > 0. {
+            for factor in self.map.values_mut() {
+                *factor /= norm;
+            }
+        }
+    }
+
+    pub fn get(&self, gp: &GridPos) -> c32 {
+        *self.map.get(gp).unwrap_or(&c32::new(0., 0.))
+    }
+
+    pub fn set(&mut self, gp: GridPos, factor: c32) {
+        /*
+         * Sets the factor at gp, removing the entry if it is (nearly) zero
+         */
+        if factor.norm_sqr() < NEGLIGIBLE_PROB {
+            // Removes value if there, does nothing if not
+            self.map.remove(&gp);
+        } else {
+            // Replaces value if already there and creates new if not
+            self.map.insert(gp, factor);
+        }
+    }
+
+    pub fn factors(&self, tiles: &[GridPos]) -> Vec<c32> {
+        tiles.iter().map(|gp| self.get(gp)).collect()
+    }
+
+    fn set_all(&mut self, tiles: &[GridPos], factors: Vec<c32>) {
+        for (gp, factor) in tiles.iter().zip(factors) {
+            self.set(*gp, factor);
+        }
+    }
+
+    pub fn switch(&mut self, gp1: GridPos, gp2: GridPos) {
+        let a_i = self.get(&gp1);
+        let b_i = self.get(&gp2);
+        self.set(gp1, b_i);
+        self.set(gp2, a_i);
+    }
+
+    pub fn mix(&mut self, gp1: GridPos, gp2: GridPos) {
+        /*
+         * Balanced beam splitter between the two positions
+         */
+        let a_i = self.get(&gp1);
+        let b_i = self.get(&gp2);
+        self.set(gp1, (a_i - b_i)/2_f32.sqrt());
+        self.set(gp2, (a_i + b_i)/2_f32.sqrt());
+    }
+
+    pub fn cycle(&mut self, tiles: &[GridPos]) {
+        /*
+         * Moves the factor at each tile to the next one,
+         * and the factor at the last tile to the first one
+         */
+        let n = tiles.len();
+        let factors = (0..n)
+            .map(|k| self.get(&tiles[(k + n - 1) % n]))
+            .collect();
+        self.set_all(tiles, factors);
+    }
+
+    pub fn fourier(&mut self, tiles: &[GridPos]) {
+        /*
+         * Discrete Fourier transform of the factors at the tiles,
+         * i.e. a balanced splitter with one input and output port per tile.
+         * For two tiles this is a mix with the ports swapped.
+         */
+        let n = tiles.len();
+        let old: Vec<c32> = tiles.iter().map(|gp| self.get(gp)).collect();
+        let factors = (0..n)
+            .map(|k| {
+                old.iter()
+                    .enumerate()
+                    .map(|(j, a)| a * c32::from_polar(
+                            1., -2. * std::f32::consts::PI * (j * k) as f32 / n as f32))
+                    .sum::<c32>() / (n as f32).sqrt()
+            })
+            .collect();
+        self.set_all(tiles, factors);
+    }
+
+    pub fn diffusion(&mut self, tiles: &[GridPos]) {
+        /*
+         * Reflection of the factors at the tiles about their mean,
+         * the diffusion step of Grover search
+         */
+        let old: Vec<c32> = tiles.iter().map(|gp| self.get(gp)).collect();
+        let mean = old.iter().sum::<c32>() / tiles.len() as f32;
+        let factors = old.iter().map(|a| 2. * mean - a).collect();
+        self.set_all(tiles, factors);
+    }
+
+    pub fn apply(&mut self, gate: Gate, tiles: &[GridPos]) -> Result<(), String> {
+        /*
+         * Applies a gate to the tiles, if it can act on them. Measurements
+         * need a device rather than tiles, so they do nothing here.
+         */
+        gate.check_tiles(tiles)?;
+        match gate {
+            Gate::Switch => self.switch(tiles[0], tiles[1]),
+            Gate::Mix => self.mix(tiles[0], tiles[1]),
+            Gate::Cycle => self.cycle(tiles),
+            Gate::Fourier => self.fourier(tiles),
+            Gate::Diffusion => self.diffusion(tiles),
+            Gate::Measure => (),
+        }
+        Ok(())
+    }
 }
 
 pub fn spawn_player(
     commands: &mut Commands,
-    asset_server: &Res<AssetServer>,
     state: QState,
     ) {
-
-    // Spawn children
-    let children: Vec<Entity> = state.map
-        .iter()
-        .map(|(gp, factor)| spawn_superposition(
-            commands, asset_server, *gp, *factor))
-        .collect();
-
-    // Spawn player entity
+    /*
+     * Spawns the player. The superpositions are spawned as children
+     * by update_superpositions, unless the overlay shows the state.
+     */
     commands.spawn()
+        .insert(DisplayedState(state.clone()))
         .insert(state)
         .insert(Player)
         // The transform and global transform are unused in this
@@ -121,24 +265,38 @@ pub fn spawn_player(
         // and thus the parents *have* to have a transform.
         // See https://github.com/bevyengine/bevy/issues/2730
         .insert(Transform::from_xyz(0., 0., 10.))
-        .insert(GlobalTransform::identity())
-        .push_children(&children);
+        .insert(GlobalTransform::identity());
+}
+
+fn magnitude_bar(grid: &GridConfig, factor: c32) -> (Vec2, Transform) {
+    /*
+     * The size and place of the magnitude bar of a superposition,
+     * at most 46 x 4 pixels on 64 pixel tiles
+     */
+    let half_tile = grid.tile_size / 2.;
+    let size = Vec2::new((factor.norm() * grid.px(46.)).ceil(), grid.px(4.));
+    // - half_tile because it starts from the middle of the tile
+    // + size/2 because the anchor is in the middle of the bar
+    // + 9 because the bar should be 9 pixels right of the boundary
+    let transform = Transform::from_xyz(size.x/2. - half_tile + grid.px(9.),
+                                        size.y/2. - half_tile + grid.px(5.), 2.);
+    (size, transform)
 }
 
 pub fn spawn_superposition(commands: &mut Commands,
-    asset_server: &Res<AssetServer>,
+    assets: &GameAssets,
+    grid: &GridConfig,
+    scheme: Scheme,
     gp: GridPos,
     factor: c32
     ) -> Entity {
     /* 
      * Spawns a new superposition at gp
      */
-    println!("Spawning: ");
 
     // Position in world coordinates
-    let world_pos = grid_to_world_coordinates(&gp);
-    // Barlength
-    let bar_length = (factor.norm() * 46.).ceil();
+    let world_pos = grid.grid_to_world(&gp);
+    let (bar_size, bar_transform) = magnitude_bar(grid, factor);
     let color = if factor.norm_sqr() >= 1. {
             Color::rgba(1., 1., 1., 1.)
         } else { 
@@ -146,10 +304,11 @@ pub fn spawn_superposition(commands: &mut Commands,
         };
 
     commands.spawn_bundle(SpriteBundle {
-        texture: asset_server.load("sprites/player_front.png"),
+        texture: assets.player.clone(),
         transform: Transform::from_xyz(world_pos.x, world_pos.y, 1.),
         sprite: Sprite{
             color,
+            custom_size: Some(grid.tile()),
             ..Default::default()
         },
         ..Default::default()
@@ -159,29 +318,33 @@ pub fn spawn_superposition(commands: &mut Commands,
     .with_children(|parent| {
         // Spawn bar background
         parent.spawn_bundle(SpriteBundle{
-            texture: asset_server.load("sprites/bar.png"),
+            texture: assets.bar.clone(),
+            sprite: Sprite {
+                custom_size: Some(grid.tile()),
+                ..Default::default()
+            },
             transform: Transform::from_xyz(0., 0., 1.),
             ..Default::default()
         });
         // Spawn bar
         parent.spawn_bundle(SpriteBundle{
                 sprite: Sprite {
-                    color: Color::rgb(0.7, 0.0, 0.0),
-                    custom_size: Some(Vec2::new(bar_length, 4.)),
+                    color: scheme.player_bar(),
+                    custom_size: Some(bar_size),
                     ..Default::default()
                 },
-                // -32 because it starts from the middle of the tile
-                // + 23/2 because the anchor is in the middle of the bar
-                // + 9 because the bar should be 9 pixels left of the boundry
-                transform: Transform::from_xyz(bar_length/2. - 32. + 9., 
-                                               4./2. - 32. + 5., 2.),
+                transform: bar_transform,
                 ..Default::default()
         })
         .insert(MagnitudeIndicator);
         // Spawn arrow
         parent.spawn_bundle(SpriteBundle{
-                texture: asset_server.load("sprites/arrow.png"),
-                transform: Transform::from_xyz(0., 18., 2.)
+                texture: assets.arrow.clone(),
+                sprite: Sprite {
+                    custom_size: Some(grid.art(6., 10.)),
+                    ..Default::default()
+                },
+                transform: Transform::from_xyz(0., grid.px(18.), 2.)
                     .with_rotation(Quat::from_rotation_z(factor.arg())),
                 ..Default::default()
         })
@@ -189,55 +352,67 @@ pub fn spawn_superposition(commands: &mut Commands,
     }).id()
 }
 
+#[allow(clippy::too_many_arguments)]
 pub fn update_superpositions(
     mut commands: Commands,
-    asset_server: Res<AssetServer>,
-    player_query: Query<(Entity, &QState, &Children), (Changed<QState>, With<Player>)>,
+    assets: Res<GameAssets>,
+    grid: Res<GridConfig>,
+    verbosity: Res<Verbosity>,
+    overlay: Res<WaveOverlay>,
+    scheme: Res<Scheme>,
+    player_query: Query<(Entity, &DisplayedState, Option<&Children>), With<Player>>,
+    changed_query: Query<Entity, (Changed<DisplayedState>, With<Player>)>,
     mut superposition_query: Query<(&GridPos, &mut Superposition)>
     ){
     /*
      * Update the factors in the superposition entities
-     * whenever the state changes
+     * whenever the displayed state changes. Only the superpositions that
+     * appear or disappear are spawned or despawned, and none are
+     * kept while the overlay shows the state.
      */
-    
-    for (entity, state, children) in player_query.iter() {
-        println!("QState: {:?}", state.map);
-        // Loop through children, despawn any that aren't in state
-        // and make sure the factors match in those that are
-        for child in children.iter() {
-            let (child_gp, mut child_sp) = 
-                superposition_query.get_mut(*child).unwrap();
-
-            println!("{:?}", state.map.get(child_gp));
-            if let Some(factor) = state.map.get(child_gp) {
-                if child_sp.factor != *factor {
-                    child_sp.factor = *factor;
+    if !overlay.is_changed() && changed_query.is_empty() {
+        return;
+    }
+    for (entity, DisplayedState(state), children) in player_query.iter() {
+        let show_sprites = !overlay.shows(state);
+        if verbosity.allows(Level::DEBUG) {
+            debug!(target: RENDER_SYNC, "Syncing {} superpositions", state.map.len());
+        }
+        // Update the children still in the state and despawn the others
+        let mut shown: HashMap<GridPos, Entity> = HashMap::new();
+        for child in children.into_iter().flat_map(|children| children.iter()) {
+            let (child_gp, mut child_sp) = match superposition_query.get_mut(*child) {
+                Ok(superposition) => superposition,
+                Err(_) => continue,
+            };
+            match state.map.get(child_gp) {
+                Some(factor) if show_sprites => {
+                    if child_sp.factor != *factor {
+                        child_sp.factor = *factor;
+                    }
+                    shown.insert(*child_gp, *child);
+                }
+                _ => {
+                    if verbosity.allows(Level::TRACE) {
+                        trace!(target: RENDER_SYNC, "Despawning superposition at {}", child_gp);
+                    }
+                    commands.entity(*child).despawn_recursive();
                 }
-            } else {
-                // Superposition is no longer part of the state and should
-                // be despawned. I think this removes it from the parent
-                // children list aswell.
-                println!("Despawning");
-                commands.entity(*child).despawn_recursive();
             }
         }
-        // Loop through state to see which entries do not have an
-        // associated child and spawn one from them
+        if !show_sprites {
+            continue;
+        }
+        // Spawn children for the entries of the state without one
         for (gp, factor) in state.map.iter() {
-            let mut found_child = false;
-
-            for child in children.iter() {
-                let (child_gp, _child_sp) = 
-                    superposition_query.get_mut(*child).unwrap();
-                if *child_gp == *gp {
-                    found_child = true;
-                    break;
+            if !shown.contains_key(gp) {
+                if verbosity.allows(Level::TRACE) {
+                    trace!(target: RENDER_SYNC, "Spawning superposition at {}: {}", gp, factor);
                 }
-            }
-            
-            if !found_child {
                 let id = spawn_superposition(&mut commands,
-                                             &asset_server,
+                                             &assets,
+                                             &grid,
+                                             *scheme,
                                              *gp,
                                              *factor);
                 commands.entity(entity)
@@ -247,7 +422,9 @@ pub fn update_superpositions(
     }
 }
 
+#[allow(clippy::type_complexity)]
 pub fn update_superposition_indicators(
+    grid: Res<GridConfig>,
     mut superposition_query: Query<(&Children, &mut Sprite, &Superposition),
         (Changed<Superposition>, Without<MeasurementIndicator>)>,
     mut phase_ind_q: Query<&mut Transform,
@@ -278,13 +455,104 @@ pub fn update_superposition_indicators(
                     scale: arrow_transform.scale
                 };
             }
-            // Barlength
-            let bar_length = (sp.factor.norm() * 46.).ceil();
             if let Ok((mut bar_transform, mut bar_sprite)) = magn_ind_q.get_mut(child) {
-                *bar_transform = Transform::from_xyz(bar_length/2. - 32. + 9., 
-                    4./2. - 32. + 5., 2.);
-                bar_sprite.custom_size = Some(Vec2::new(bar_length, 4.));
+                let (bar_size, transform) = magnitude_bar(&grid, sp.factor);
+                *bar_transform = transform;
+                bar_sprite.custom_size = Some(bar_size);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tiles(n: i32) -> Vec<GridPos> {
+        (0..n).map(|x| GridPos::new(x, 0)).collect()
+    }
+
+    fn state(factors: &[c32]) -> QState {
+        let mut state = QState{ map: HashMap::new() };
+        for (gp, factor) in tiles(factors.len() as i32).into_iter().zip(factors) {
+            state.set(gp, *factor);
+        }
+        state.normalize();
+        state
+    }
+
+    // A state with a different magnitude and phase on each of four tiles
+    fn uneven() -> QState {
+        state(&[c32::new(0.1, 0.5), c32::new(-0.7, 0.2), c32::new(0.3, 0.), c32::new(0., -0.4)])
+    }
+
+    fn norm_sqr(state: &QState) -> f32 {
+        state.map.values().map(|factor| factor.norm_sqr()).sum()
+    }
+
+    fn assert_near(a: &QState, b: &QState) {
+        for gp in a.map.keys().chain(b.map.keys()) {
+            assert!((a.get(gp) - b.get(gp)).norm() < 1e-5,
+                    "{:?} differs at {}: {} vs {}", a.map, gp, a.get(gp), b.get(gp));
+        }
+    }
+
+    #[test]
+    fn gates_keep_the_norm() {
+        for gate in [Gate::Switch, Gate::Mix, Gate::Cycle, Gate::Fourier, Gate::Diffusion] {
+            for k in 2..=4 {
+                if gate.check_tiles(&tiles(k)).is_err() {
+                    continue;
+                }
+                let mut state = uneven();
+                state.apply(gate, &tiles(k)).unwrap();
+                assert!((norm_sqr(&state) - 1.).abs() < 1e-5, "{} on {} tiles", gate.name(), k);
+            }
+        }
+    }
+
+    #[test]
+    fn cycling_k_times_gives_the_state_back() {
+        for k in 2..=4 {
+            let mut state = uneven();
+            for _ in 0..k {
+                state.apply(Gate::Cycle, &tiles(k)).unwrap();
             }
+            assert_near(&state, &uneven());
+        }
+    }
+
+    #[test]
+    fn fourier_on_two_tiles_is_a_mix_with_the_ports_swapped() {
+        let pair = tiles(2);
+        let mut fourier = state(&[c32::new(0.6, 0.), c32::new(0., 0.8)]);
+        let mut mix = fourier.clone();
+        fourier.apply(Gate::Fourier, &pair).unwrap();
+        mix.mix(pair[0], pair[1]);
+        mix.switch(pair[0], pair[1]);
+        assert_near(&fourier, &mix);
+    }
+
+    #[test]
+    fn diffusion_twice_is_the_identity() {
+        for k in 2..=4 {
+            let mut state = uneven();
+            state.apply(Gate::Diffusion, &tiles(k)).unwrap();
+            state.apply(Gate::Diffusion, &tiles(k)).unwrap();
+            assert_near(&state, &uneven());
         }
     }
+
+    #[test]
+    fn gates_need_the_right_tiles() {
+        let mut state = uneven();
+        assert!(state.apply(Gate::Switch, &tiles(1)).is_err());
+        assert!(state.apply(Gate::Mix, &tiles(3)).is_err());
+        assert!(state.apply(Gate::Cycle, &tiles(5)).is_err());
+        assert!(state.apply(Gate::Diffusion, &[]).is_err());
+        let twice = [GridPos::new(1, 0), GridPos::new(0, 0), GridPos::new(1, 0)];
+        assert!(state.apply(Gate::Fourier, &twice).is_err());
+        // Nothing was applied
+        assert_near(&state, &uneven());
+    }
 }
@@ -0,0 +1,130 @@
+use std::collections::HashSet;
+use bevy::prelude::*;
+use num::complex;
+
+use crate::actions::Action;
+use crate::loading::GameAssets;
+use super::animation::DisplayedState;
+use super::coords::*;
+use super::player::Player;
+use super::scheme::Scheme;
+#[allow(non_camel_case_types)]
+type c32 = complex::Complex32;
+
+// Drawn above the player and the collapse effects, below the selection
+const READOUT_Z: f32 = 16.;
+
+/*
+ * Components
+ */
+#[derive(Component)]
+pub struct ReadoutText;
+
+/*
+ * Resources
+ */
+// Whether each position of the player is labelled with its
+// probability and phase in numbers
+#[derive(Default)]
+pub struct Readout {
+    pub enabled: bool,
+}
+
+fn readout_text(factor: c32) -> String {
+    /*
+     * The probability in percent over the phase in degrees,
+     * e.g. 50% over -90°
+     */
+    let percent = factor.norm_sqr() * 100.;
+    let probability = if percent > 0. && percent < 0.5 {
+        "<1%".to_string()
+    } else {
+        format!("{:.0}%", percent)
+    };
+    format!("{}\n{:.0}°", probability, factor.arg().to_degrees())
+}
+
+fn readout_style(assets: &GameAssets, scheme: Scheme) -> TextStyle {
+    // High contrast is written in bold
+    let font = match scheme {
+        Scheme::HighContrast => &assets.bold_font,
+        _ => &assets.font,
+    };
+    TextStyle {
+        font: font.clone(),
+        font_size: 14.0,
+        color: scheme.readout(),
+    }
+}
+
+/*
+ * Systems
+ */
+pub fn toggle_readout(actions: Res<Input<Action>>, mut readout: ResMut<Readout>) {
+    if actions.just_pressed(Action::ToggleReadout) {
+        readout.enabled = !readout.enabled;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn update_readouts(
+    mut commands: Commands,
+    assets: Res<GameAssets>,
+    grid: Res<GridConfig>,
+    readout: Res<Readout>,
+    scheme: Res<Scheme>,
+    player_query: Query<&DisplayedState, With<Player>>,
+    changed_query: Query<Entity, (Changed<DisplayedState>, With<Player>)>,
+    mut text_query: Query<(Entity, &GridPos, &mut Text), With<ReadoutText>>,
+    ) {
+    /*
+     * Keeps a label on every position of the displayed state while the
+     * readout is on, updating the labels still needed and despawning
+     * the others
+     */
+    if !readout.is_changed() && !scheme.is_changed() && changed_query.is_empty() {
+        return;
+    }
+    let state = match player_query.get_single() {
+        Ok(DisplayedState(state)) => state,
+        Err(_) => return,
+    };
+    let style = readout_style(&assets, *scheme);
+
+    let mut labelled = HashSet::new();
+    for (entity, gp, mut text) in text_query.iter_mut() {
+        match state.map.get(gp) {
+            Some(factor) if readout.enabled => {
+                let value = readout_text(*factor);
+                if text.sections[0].value != value {
+                    text.sections[0].value = value;
+                }
+                if scheme.is_changed() {
+                    text.sections[0].style = style.clone();
+                }
+                labelled.insert(*gp);
+            }
+            _ => commands.entity(entity).despawn(),
+        }
+    }
+    if !readout.enabled {
+        return;
+    }
+    for (gp, factor) in state.map.iter().filter(|(gp, _)| !labelled.contains(gp)) {
+        let world_pos = grid.grid_to_world(gp);
+        commands.spawn_bundle(Text2dBundle {
+            text: Text::with_section(
+                readout_text(*factor),
+                style.clone(),
+                TextAlignment {
+                    vertical: VerticalAlign::Center,
+                    horizontal: HorizontalAlign::Center,
+                },
+            ),
+            transform: Transform::from_xyz(world_pos.x, world_pos.y, READOUT_Z),
+            ..Default::default()
+        })
+        .insert(ReadoutText)
+        .insert(*gp);
+    }
+}
@@ -0,0 +1,431 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+use bevy::prelude::*;
+use bevy::app::AppExit;
+use serde::{Serialize, Deserialize};
+
+use crate::AppState;
+use crate::actions::Action;
+use crate::loading::GameAssets;
+use super::coords::*;
+use super::goal::LevelCompleteBanner;
+use super::level::{Gate, GateInventory};
+use super::measurer::MeasurementDevice;
+use super::operations::*;
+use super::player::QState;
+use super::selection::{ClearSelectionEvent, SelectTileEvent};
+use super::solver::Move;
+
+// Directory, relative to the working directory, that replays are saved to
+const REPLAY_DIR: &str = "replays";
+// Seconds between steps when playing a replay back
+const STEP_SECONDS: f32 = 0.6;
+
+/*
+ * Components
+ */
+#[derive(Component)]
+pub struct ReplayText;
+
+/*
+ * Replays
+ */
+#[derive(Clone, Serialize, Deserialize)]
+pub enum ReplayAction {
+    Select(GridPos),
+    ClearSelection,
+    // A gate or a measurement, which lists the tiles of the device
+    Operation(Move),
+    Undo,
+    // The measurements were seeded anew, e.g. from the console
+    Seed(u64),
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ReplayStep {
+    // The operations done before the step, plus one, so that the operation
+    // of a step has the same turn as in the operation log
+    pub turn: usize,
+    pub action: ReplayAction,
+}
+
+// Everything needed to play a run of a level again, with identical outcomes
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Replay {
+    pub level: String,
+    pub seed: u64,
+    pub steps: Vec<ReplayStep>,
+}
+
+impl Replay {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read {}: {}", path.display(), e))?;
+        Replay::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        /*
+         * Reads a replay, checking that every gate acts on tiles it can,
+         * as replays may have been cut short or edited by hand
+         */
+        let replay: Replay = ron::from_str(text).map_err(|e| e.to_string())?;
+        for (i, step) in replay.steps.iter().enumerate() {
+            if let ReplayAction::Operation(Move{ gate, tiles }) = &step.action {
+                gate.check_tiles(tiles).map_err(|e| format!("step {}: {}", i + 1, e))?;
+            }
+        }
+        Ok(replay)
+    }
+}
+
+/*
+ * Resources
+ */
+// Records the run of the current level in memory, saving it when the
+// level is completed or left, and when the game quits
+pub struct ReplayRecorder {
+    replay: Replay,
+    path: PathBuf,
+    turn: usize,
+    // Steps already written to the file
+    saved_steps: usize,
+    // The seed measurements are currently drawn with
+    seed: u64,
+}
+
+impl ReplayRecorder {
+    pub fn new(level: &str, seed: u64) -> Self {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs());
+        ReplayRecorder{
+            replay: Replay{ level: level.to_string(), seed, steps: Vec::new() },
+            path: PathBuf::from(REPLAY_DIR).join(format!("{}-{}.ron", level, seconds)),
+            turn: 1,
+            saved_steps: 0,
+            seed,
+        }
+    }
+
+    fn push(&mut self, action: ReplayAction) {
+        let ends_turn = matches!(action, ReplayAction::Operation(_) | ReplayAction::Undo);
+        self.replay.steps.push(ReplayStep{ turn: self.turn, action });
+        if ends_turn {
+            self.turn += 1;
+        }
+    }
+
+    fn save(&mut self) -> Result<(), String> {
+        /*
+         * Writes the replay, unless nothing was recorded since it last was
+         */
+        if self.saved_steps == self.replay.steps.len() {
+            return Ok(());
+        }
+        let text = ron::ser::to_string_pretty(
+            &self.replay, ron::ser::PrettyConfig::new().depth_limit(3))
+            .map_err(|e| e.to_string())?;
+        fs::create_dir_all(REPLAY_DIR).map_err(|e| e.to_string())?;
+        fs::write(&self.path, text).map_err(|e| e.to_string())?;
+        self.saved_steps = self.replay.steps.len();
+        Ok(())
+    }
+}
+
+// Present while a replay is played back, in place of the player's input
+pub struct Playback {
+    pub replay: Replay,
+    // Steps applied so far
+    position: usize,
+    // The step to hurry to, after scrubbing back or forward
+    target: Option<usize>,
+    paused: bool,
+    timer: Timer,
+}
+
+impl Playback {
+    pub fn new(replay: Replay) -> Self {
+        Playback{
+            replay,
+            position: 0,
+            target: None,
+            paused: false,
+            timer: Timer::from_seconds(STEP_SECONDS, true),
+        }
+    }
+}
+
+pub fn spawn_replay_text(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    ) {
+    /*
+     * Spawns the text showing how far the replay is,
+     * filled in by update_replay_text
+     */
+    commands.spawn_bundle(TextBundle {
+        style: Style {
+            position_type: PositionType::Absolute,
+            position: Rect {
+                left: Val::Percent(35.0),
+                bottom: Val::Px(10.0),
+                ..Default::default()
+            },
+            ..Default::default()
+        },
+        text: Text::with_section(
+            "",
+            TextStyle {
+                font: assets.font.clone(),
+                font_size: 24.0,
+                color: Color::rgb(1.0, 0.9, 0.5),
+            },
+            Default::default(),
+        ),
+        ..Default::default()
+    })
+    .insert(ReplayText);
+}
+
+/*
+ * Systems
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn record_replay(
+    recorder: Option<ResMut<ReplayRecorder>>,
+    rng: Res<GameRng>,
+    device_query: Query<&QState, With<MeasurementDevice>>,
+    mut select_reader: EventReader<SelectTileEvent>,
+    mut clear_reader: EventReader<ClearSelectionEvent>,
+    mut switch_reader: EventReader<SwitchEvent>,
+    mut mix_reader: EventReader<MixEvent>,
+    mut cycle_reader: EventReader<CycleEvent>,
+    mut fourier_reader: EventReader<FourierEvent>,
+    mut diffusion_reader: EventReader<DiffusionEvent>,
+    mut measure_reader: EventReader<MeasureEvent>,
+    mut undo_reader: EventReader<UndoEvent>,
+    ) {
+    /*
+     * Adds the selections and operations of the frame to the replay.
+     * Nothing is recorded while a replay is played back.
+     */
+    let mut recorder = match recorder {
+        Some(recorder) => recorder,
+        None => return,
+    };
+    let mut actions = Vec::new();
+    if rng.seed != recorder.seed {
+        recorder.seed = rng.seed;
+        actions.push(ReplayAction::Seed(rng.seed));
+    }
+    actions.extend(select_reader.iter().map(|event| ReplayAction::Select(event.gp)));
+    let operation = |gate, tiles: &[GridPos]| ReplayAction::Operation(Move{ gate, tiles: tiles.to_vec() });
+    actions.extend(switch_reader.iter().map(|event| operation(Gate::Switch, &[event.gp1, event.gp2])));
+    actions.extend(mix_reader.iter().map(|event| operation(Gate::Mix, &[event.gp1, event.gp2])));
+    actions.extend(cycle_reader.iter().map(|event| operation(Gate::Cycle, &event.tiles)));
+    actions.extend(fourier_reader.iter().map(|event| operation(Gate::Fourier, &event.tiles)));
+    actions.extend(diffusion_reader.iter().map(|event| operation(Gate::Diffusion, &event.tiles)));
+    for event in measure_reader.iter() {
+        if let Ok(device_state) = device_query.get(event.entity) {
+            let mut tiles: Vec<GridPos> = device_state.map.keys().copied().collect();
+            tiles.sort();
+            actions.push(operation(Gate::Measure, &tiles));
+        }
+    }
+    actions.extend(undo_reader.iter().map(|_| ReplayAction::Undo));
+    actions.extend(clear_reader.iter().map(|_| ReplayAction::ClearSelection));
+
+    for action in actions {
+        recorder.push(action);
+    }
+}
+
+fn save(recorder: Option<ResMut<ReplayRecorder>>) {
+    if let Some(mut recorder) = recorder {
+        if let Err(e) = recorder.save() {
+            warn!("Could not save the replay: {}", e);
+        }
+    }
+}
+
+pub fn save_replay(recorder: Option<ResMut<ReplayRecorder>>) {
+    /*
+     * Saves the replay when the level is left,
+     * e.g. for the menu or to play it again
+     */
+    save(recorder);
+}
+
+pub fn save_completed_replay(
+    recorder: Option<ResMut<ReplayRecorder>>,
+    banner_query: Query<(), Added<LevelCompleteBanner>>,
+    ) {
+    /*
+     * Saves the replay once the level is completed
+     */
+    if !banner_query.is_empty() {
+        save(recorder);
+    }
+}
+
+pub fn save_replay_on_exit(
+    mut exit_reader: EventReader<AppExit>,
+    recorder: Option<ResMut<ReplayRecorder>>,
+    ) {
+    /*
+     * Saves the replay when the game quits, e.g. by closing the window.
+     * Runs last in the frame, as the game stops right after it.
+     */
+    if exit_reader.iter().next().is_some() {
+        save(recorder);
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn play_replay(
+    time: Res<Time>,
+    actions: Res<Input<Action>>,
+    playback: Option<ResMut<Playback>>,
+    mut state: ResMut<State<AppState>>,
+    mut rng: ResMut<GameRng>,
+    mut inventory: ResMut<GateInventory>,
+    device_query: Query<(Entity, &QState), With<MeasurementDevice>>,
+    mut select_writer: EventWriter<SelectTileEvent>,
+    mut clear_writer: EventWriter<ClearSelectionEvent>,
+    mut operation_writers: OperationWriters,
+    ) {
+    /*
+     * Applies the steps of the replay one at a time, sending the same
+     * events as the player's input did. Scrubbing back sets the level up
+     * again and hurries through the steps up to the one scrubbed to,
+     * one per frame, so that the seeded measurements come out the same.
+     */
+    let mut playback = match playback {
+        Some(playback) => playback,
+        None => return,
+    };
+    if actions.just_pressed(Action::ReplayPause) {
+        playback.paused = !playback.paused;
+    }
+    if actions.just_pressed(Action::ReplayForward) {
+        let next = playback.target.unwrap_or(playback.position) + 1;
+        playback.target = Some(next.min(playback.replay.steps.len()));
+        playback.paused = true;
+    }
+    if actions.just_pressed(Action::ReplayBack) && playback.position > 0 {
+        playback.target = Some(playback.target.unwrap_or(playback.position) - 1);
+        playback.position = 0;
+        playback.paused = true;
+        state.set(AppState::Reloading).unwrap();
+        return;
+    }
+
+    playback.timer.tick(time.delta());
+    let due = match playback.target {
+        Some(target) => playback.position < target,
+        None => !playback.paused && playback.timer.just_finished(),
+    };
+    if playback.target.map_or(false, |target| playback.position >= target) {
+        playback.target = None;
+    }
+    if !due {
+        return;
+    }
+    let step = match playback.replay.steps.get(playback.position) {
+        Some(step) => step.clone(),
+        None => return,
+    };
+    playback.position += 1;
+
+    match step.action {
+        ReplayAction::Select(gp) => select_writer.send(SelectTileEvent{ gp }),
+        ReplayAction::ClearSelection => clear_writer.send(ClearSelectionEvent),
+        ReplayAction::Operation(Move{ gate, tiles }) => {
+            if let Err(e) = gate.check_tiles(&tiles) {
+                warn!("Skipped a replay step: {}", e);
+                return;
+            }
+            // The gate is used up as in play, but it is applied even if the
+            // inventory disagrees, e.g. for operations done in the console
+            inventory.try_use(gate);
+            if gate == Gate::Measure {
+                let device = device_query.iter()
+                    .find(|(_, device_state)| device_state.map.len() == tiles.len()
+                          && tiles.iter().all(|gp| device_state.map.contains_key(gp)));
+                match device {
+                    Some((entity, _)) => operation_writers.measure.send(MeasureEvent{ entity }),
+                    None => warn!("No measurement device at the replayed tiles"),
+                }
+            } else {
+                if let Err(e) = operation_writers.send_gate(gate, tiles) {
+                    warn!("Skipped a replay step: {}", e);
+                }
+            }
+        }
+        ReplayAction::Undo => operation_writers.undo.send(UndoEvent),
+        ReplayAction::Seed(seed) => *rng = GameRng::new(seed),
+    }
+}
+
+pub fn update_replay_text(
+    playback: Option<Res<Playback>>,
+    mut text_query: Query<&mut Text, With<ReplayText>>,
+    ) {
+    /*
+     * Shows how far the replay is and whether it is paused
+     */
+    let playback = match playback {
+        Some(playback) if playback.is_changed() => playback,
+        _ => return,
+    };
+    let steps = playback.replay.steps.len();
+    let turn = playback.position.checked_sub(1)
+        .and_then(|i| playback.replay.steps.get(i))
+        .map_or(0, |step| step.turn);
+    let status = if playback.position >= steps {
+        "finished"
+    } else if playback.paused {
+        "paused"
+    } else {
+        "playing"
+    };
+    for mut text in text_query.iter_mut() {
+        text.sections[0].value = format!(
+            "Replay of {}: step {}/{}, turn {} ({})",
+            playback.replay.level, playback.position, steps, turn, status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replay(action: &str) -> String {
+        format!("(level: \"first\", seed: 7, steps: [(turn: 1, action: {})])", action)
+    }
+
+    #[test]
+    fn parse_replay() {
+        let text = replay("Operation((gate: Mix, tiles: [(x: 1, y: 0), (x: 2, y: 0)]))");
+        let replay = Replay::parse(&text).unwrap();
+        assert_eq!(replay.seed, 7);
+        assert_eq!(replay.steps.len(), 1);
+    }
+
+    #[test]
+    fn reject_malformed_replays() {
+        let malformed = [
+            "Operation((gate: Switch, tiles: [(x: 1, y: 0)]))",
+            "Operation((gate: Mix, tiles: []))",
+            "Operation((gate: Cycle, tiles: [(x: 0, y: 0), (x: 1, y: 0), (x: 2, y: 0), (x: 3, y: 0), (x: 4, y: 0)]))",
+            "Operation((gate: Fourier, tiles: [(x: 1, y: 0), (x: 1, y: 0)]))",
+            "Operation((gate: Measure, tiles: []))",
+        ];
+        for action in malformed {
+            assert!(Replay::parse(&replay(action)).is_err(), "{} was accepted", action);
+        }
+        assert!(Replay::parse("(level: \"first\", seed: 7, steps: [(turn: 1, ").is_err());
+    }
+}
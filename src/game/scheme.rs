@@ -0,0 +1,134 @@
+use std::f32::consts::PI;
+use bevy::prelude::*;
+use num::complex;
+
+use crate::actions::Action;
+use super::measurer::DeviceBar;
+use super::player::MagnitudeIndicator;
+#[allow(non_camel_case_types)]
+type c32 = complex::Complex32;
+
+// The colors phases go through with the colorblind scheme, starting at
+// positive real factors and turning counterclockwise. These are from
+// the Okabe-Ito palette: orange, yellow, blue and reddish purple.
+const COLORBLIND_PHASES: [(f32, f32, f32); 4] = [
+    (0.90, 0.62, 0.0),
+    (0.94, 0.89, 0.26),
+    (0.0, 0.45, 0.70),
+    (0.80, 0.47, 0.65),
+];
+
+/*
+ * Resources
+ */
+// The colors amplitudes are drawn with
+#[derive(Clone, Copy, PartialEq, Eq, Default)]
+pub enum Scheme {
+    #[default]
+    Classic,
+    // Colors told apart with every common kind of color blindness
+    Colorblind,
+    HighContrast,
+}
+
+impl Scheme {
+    pub fn next(self) -> Self {
+        match self {
+            Scheme::Classic => Scheme::Colorblind,
+            Scheme::Colorblind => Scheme::HighContrast,
+            Scheme::HighContrast => Scheme::Classic,
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Scheme::Classic => "classic",
+            Scheme::Colorblind => "colorblind",
+            Scheme::HighContrast => "high contrast",
+        }
+    }
+
+    // The magnitude bars of the player
+    pub fn player_bar(self) -> Color {
+        match self {
+            Scheme::Classic => Color::rgb(0.7, 0.0, 0.0),
+            Scheme::Colorblind => Color::rgb(0.90, 0.62, 0.0),
+            Scheme::HighContrast => Color::rgb(1.0, 1.0, 0.0),
+        }
+    }
+
+    // The magnitude bars of the measurement devices
+    pub fn device_bar(self) -> Color {
+        match self {
+            Scheme::Classic => Color::rgb(0.2, 0.87, 0.08),
+            Scheme::Colorblind => Color::rgb(0.0, 0.45, 0.70),
+            Scheme::HighContrast => Color::rgb(0.0, 1.0, 1.0),
+        }
+    }
+
+    // The numbers written on tiles by the readout
+    pub fn readout(self) -> Color {
+        match self {
+            Scheme::HighContrast => Color::rgb(1.0, 1.0, 0.0),
+            _ => Color::WHITE,
+        }
+    }
+
+    pub fn phase_color(self, factor: c32) -> Color {
+        /*
+         * Colors a factor as in domain coloring: the hue is the phase
+         * and the transparency the probability. The classic scheme starts
+         * at red for positive real factors, the colorblind one blends
+         * between its four colors. Even the least likely positions
+         * are tinted a little, more so with high contrast.
+         */
+        let prob = factor.norm_sqr();
+        if prob == 0. {
+            return Color::NONE;
+        }
+        let min_alpha = if self == Scheme::HighContrast { 0.35 } else { 0.1 };
+        let alpha = min_alpha + (1. - min_alpha) * prob.min(1.);
+        // The phase in turns, from 0 to 1
+        let turns = (factor.arg() + 2. * PI) % (2. * PI) / (2. * PI);
+        match self {
+            Scheme::Classic | Scheme::HighContrast => Color::hsla(turns * 360., 1.0, 0.5, alpha),
+            Scheme::Colorblind => {
+                let n = COLORBLIND_PHASES.len();
+                let position = turns * n as f32;
+                let (r1, g1, b1) = COLORBLIND_PHASES[position as usize % n];
+                let (r2, g2, b2) = COLORBLIND_PHASES[(position as usize + 1) % n];
+                let s = position.fract();
+                Color::rgba(r1 + (r2 - r1) * s, g1 + (g2 - g1) * s, b1 + (b2 - b1) * s, alpha)
+            }
+        }
+    }
+}
+
+/*
+ * Systems
+ */
+pub fn cycle_scheme(actions: Res<Input<Action>>, mut scheme: ResMut<Scheme>) {
+    if actions.just_pressed(Action::CycleColorScheme) {
+        *scheme = scheme.next();
+        info!("Color scheme: {}", scheme.name());
+    }
+}
+
+pub fn recolor_bars(
+    scheme: Res<Scheme>,
+    mut player_bar_query: Query<&mut Sprite, (With<MagnitudeIndicator>, Without<DeviceBar>)>,
+    mut device_bar_query: Query<&mut Sprite, With<DeviceBar>>,
+    ) {
+    /*
+     * Recolors the magnitude bars already spawned when the scheme changes
+     */
+    if !scheme.is_changed() {
+        return;
+    }
+    for mut sprite in player_bar_query.iter_mut() {
+        sprite.color = scheme.player_bar();
+    }
+    for mut sprite in device_bar_query.iter_mut() {
+        sprite.color = scheme.device_bar();
+    }
+}
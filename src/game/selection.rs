@@ -0,0 +1,314 @@
+use bevy::prelude::*;
+use bevy::render::camera::Camera;
+use bevy_ecs_tilemap::prelude::*;
+
+use crate::actions::Action;
+use crate::loading::GameAssets;
+use super::coords::*;
+use super::operations::Blocking;
+use super::level::MAX_GATE_TILES;
+use super::picking::cursor_world_position;
+use super::replay::Playback;
+use super::animation::GateAnimation;
+use super::MainCamera;
+
+/*
+ * Components
+ */
+#[derive(Component)]
+pub struct SelectedPos;
+// Marks a tile that can be added to the current selection
+#[derive(Component)]
+pub struct SelectableHighlight;
+
+/*
+ * Events
+ */
+pub struct SelectTileEvent{
+    pub gp: GridPos,
+}
+pub struct ClearSelectionEvent;
+
+/*
+ * Resources
+ */
+#[derive(Default)]
+pub struct Selection {
+    // The selected tiles, in the order they were selected
+    pub tiles: Vec<GridPos>,
+}
+
+pub struct SelectionRules {
+    // The largest number of tiles any gate acts on
+    pub max_tiles: usize,
+}
+
+impl Default for SelectionRules {
+    fn default() -> Self {
+        SelectionRules{ max_tiles: MAX_GATE_TILES }
+    }
+}
+
+impl Selection {
+    pub fn can_add(&self, gp: &GridPos, rules: &SelectionRules) -> bool {
+        /*
+         * A tile can be added if it is not already selected, there is room
+         * for it, and it neighbours some selected tile (or is the first one).
+         * Whether the tile itself is selectable is up to the caller.
+         */
+        !self.tiles.contains(gp)
+            && self.tiles.len() < rules.max_tiles
+            && (self.tiles.is_empty()
+                || self.tiles.iter().any(|selected| are_neighbours(gp, selected)))
+    }
+
+    pub fn toggle(&mut self, gp: GridPos, rules: &SelectionRules) {
+        /*
+         * Deselects gp if selected and otherwise selects it if allowed.
+         * Deselecting a tile can split the selection in several pieces,
+         * in which case only the piece with the earliest selected tile is kept,
+         * so that the selection always stays connected.
+         */
+        if let Some(index) = self.tiles.iter().position(|selected| *selected == gp) {
+            self.tiles.remove(index);
+            self.tiles = connected_to_first(&self.tiles);
+        } else if self.can_add(&gp, rules) {
+            self.tiles.push(gp);
+        }
+    }
+}
+
+fn connected_to_first(tiles: &[GridPos]) -> Vec<GridPos> {
+    /*
+     * The tiles connected to the first tile through chains of neighbours,
+     * keeping their order
+     */
+    let mut connected = vec![false; tiles.len()];
+    if let Some(first) = connected.first_mut() {
+        *first = true;
+    }
+    // Grow the connected set until nothing more can be added
+    let mut grew = true;
+    while grew {
+        grew = false;
+        for i in 0..tiles.len() {
+            if connected[i] {
+                continue;
+            }
+            let touches_connected = tiles.iter()
+                .zip(connected.iter())
+                .any(|(other, other_connected)|
+                     *other_connected && are_neighbours(&tiles[i], other));
+            if touches_connected {
+                connected[i] = true;
+                grew = true;
+            }
+        }
+    }
+    tiles.iter()
+        .zip(connected)
+        .filter(|(_, connected)| *connected)
+        .map(|(gp, _)| *gp)
+        .collect()
+}
+
+fn is_selectable(
+    gp: &GridPos,
+    tile_query: &Query<&TilePos, With<Tile>>,
+    blocking_query: &Query<&GridPos, With<Blocking>>,
+    ) -> bool {
+    /*
+     * A position is selectable if there is a tile there
+     * with no blocking element on top of it
+     */
+    tile_query.iter().any(|tp| *tp == *gp)
+        && !blocking_query.iter().any(|blocking_gp| blocking_gp == gp)
+}
+
+/*
+ * Systems
+ */
+#[allow(clippy::too_many_arguments)]
+pub fn select_positions(
+    windows: Res<Windows>,
+    grid: Res<GridConfig>,
+    actions: Res<Input<Action>>,
+    playback: Option<Res<Playback>>,
+    animation: Res<GateAnimation>,
+    camera_query: Query<(&Camera, &GlobalTransform), With<MainCamera>>,
+    mut select_tile_writer: EventWriter<SelectTileEvent>,
+    mut clear_selection_writer: EventWriter<ClearSelectionEvent>,
+    ) {
+    /*
+     * Selects the tile under the mouse cursor when clicking,
+     * unless a replay is selecting instead or a gate is animated
+     */
+    if playback.is_some() || animation.running() {
+        return;
+    }
+    if actions.just_released(Action::Select) {
+        if let Some(world_pos) = cursor_world_position(&windows, &camera_query) {
+            select_tile_writer.send(SelectTileEvent{
+                gp: grid.world_to_grid(&world_pos) });
+        }
+    }
+    if actions.just_pressed(Action::ClearSelection) {
+        clear_selection_writer.send(ClearSelectionEvent);
+    }
+}
+
+pub fn apply_selection(
+    mut select_tile_reader: EventReader<SelectTileEvent>,
+    mut selection: ResMut<Selection>,
+    rules: Res<SelectionRules>,
+    tile_query: Query<&TilePos, With<Tile>>,
+    blocking_query: Query<&GridPos, With<Blocking>>,
+    ) {
+    /*
+     * Toggles the selection of tiles, no matter where the
+     * request came from (mouse, tile cursor, ...)
+     */
+    for event in select_tile_reader.iter() {
+        if is_selectable(&event.gp, &tile_query, &blocking_query) {
+            selection.toggle(event.gp, &rules);
+        }
+    }
+}
+
+pub fn clear_selection(
+    mut selection: ResMut<Selection>,
+    mut clear_selection_ev: EventReader<ClearSelectionEvent>
+    ) {
+    for _ in clear_selection_ev.iter() {
+        selection.tiles.clear();
+    }
+}
+
+#[allow(clippy::too_many_arguments, clippy::type_complexity)]
+pub fn show_selection(mut commands: Commands,
+    assets: Res<GameAssets>,
+    grid: Res<GridConfig>,
+    selection: Res<Selection>,
+    rules: Res<SelectionRules>,
+    marker_query: Query<Entity, Or<(With<SelectedPos>, With<SelectableHighlight>)>>,
+    tile_query: Query<&TilePos, With<Tile>>,
+    blocking_query: Query<&GridPos, With<Blocking>>,
+    ) {
+    /*
+     * Marks the selected tiles, and faintly the tiles that
+     * can be selected next, whenever the selection changes
+     */
+    if !selection.is_changed() {
+        return;
+    }
+    for entity in marker_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+
+    for gp in selection.tiles.iter() {
+        let world_pos = grid.grid_to_world(gp);
+        commands.spawn_bundle(SpriteBundle {
+                texture: assets.select.clone(),
+                sprite: Sprite {
+                    custom_size: Some(grid.tile()),
+                    ..Default::default()
+                },
+                transform: Transform::from_xyz(world_pos.x, world_pos.y, 20.),
+                ..Default::default()
+            })
+            .insert(SelectedPos)
+            .insert(*gp);
+    }
+
+    // Every tile can be selected when nothing is,
+    // so highlighting them all would just be noise
+    if selection.tiles.is_empty() {
+        return;
+    }
+    let mut candidates = Vec::new();
+    for selected in selection.tiles.iter() {
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let gp = GridPos::new(selected.x() + dx, selected.y() + dy);
+                if !candidates.contains(&gp) {
+                    candidates.push(gp);
+                }
+            }
+        }
+    }
+    for gp in candidates {
+        if selection.can_add(&gp, &rules) && is_selectable(&gp, &tile_query, &blocking_query) {
+            let world_pos = grid.grid_to_world(&gp);
+            commands.spawn_bundle(SpriteBundle {
+                    texture: assets.select.clone(),
+                    sprite: Sprite {
+                        color: Color::rgba(1., 1., 1., 0.3),
+                        custom_size: Some(grid.tile()),
+                        ..Default::default()
+                    },
+                    transform: Transform::from_xyz(world_pos.x, world_pos.y, 19.),
+                    ..Default::default()
+                })
+                .insert(SelectableHighlight)
+                .insert(gp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selection(tiles: &[(i32, i32)]) -> Selection {
+        let rules = SelectionRules::default();
+        let mut selection = Selection::default();
+        for (x, y) in tiles {
+            selection.toggle(GridPos::new(*x, *y), &rules);
+        }
+        selection
+    }
+
+    fn positions(tiles: &[(i32, i32)]) -> Vec<GridPos> {
+        tiles.iter().map(|(x, y)| GridPos::new(*x, *y)).collect()
+    }
+
+    #[test]
+    fn deselecting_the_middle_keeps_the_earliest_piece() {
+        let mut chain = selection(&[(2, 0), (1, 0), (0, 0)]);
+        chain.toggle(GridPos::new(1, 0), &SelectionRules::default());
+        assert_eq!(chain.tiles, positions(&[(2, 0)]));
+
+        // Even when the other piece is larger
+        let mut chain = selection(&[(0, 0), (1, 0), (2, 0), (3, 0)]);
+        chain.toggle(GridPos::new(1, 0), &SelectionRules::default());
+        assert_eq!(chain.tiles, positions(&[(0, 0)]));
+    }
+
+    #[test]
+    fn deselecting_the_first_tile() {
+        let mut chain = selection(&[(0, 0), (1, 0), (2, 1)]);
+        chain.toggle(GridPos::new(0, 0), &SelectionRules::default());
+        assert_eq!(chain.tiles, positions(&[(1, 0), (2, 1)]));
+    }
+
+    #[test]
+    fn can_add_stops_at_max_tiles() {
+        let rules = SelectionRules{ max_tiles: 2 };
+        let mut pair = Selection::default();
+        pair.toggle(GridPos::new(0, 0), &rules);
+        pair.toggle(GridPos::new(1, 0), &rules);
+        assert!(!pair.can_add(&GridPos::new(2, 0), &rules));
+        pair.toggle(GridPos::new(2, 0), &rules);
+        assert_eq!(pair.tiles, positions(&[(0, 0), (1, 0)]));
+    }
+
+    #[test]
+    fn can_add_only_neighbours() {
+        let rules = SelectionRules::default();
+        let single = selection(&[(0, 0)]);
+        assert!(single.can_add(&GridPos::new(1, 1), &rules));
+        assert!(!single.can_add(&GridPos::new(2, 0), &rules));
+        assert!(!single.can_add(&GridPos::new(0, 0), &rules));
+        assert!(Selection::default().can_add(&GridPos::new(5, 5), &rules));
+    }
+}
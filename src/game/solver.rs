@@ -0,0 +1,641 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::path::Path;
+use serde::{Serialize, Deserialize};
+
+use super::coords::*;
+use super::goal;
+use super::level::{Gate, GateInventory, Level, level_names};
+use super::player::QState;
+use super::selection::{Selection, SelectionRules};
+
+// Factors are compared rounded to this many parts per unit,
+// so that rounding errors do not make equal states look different
+const ROUNDING: f32 = 1e4;
+// Branches less likely than this are not worth following
+const NEGLIGIBLE_BRANCH: f32 = 1e-6;
+// More operations than any search would allow
+const UNREACHABLE: usize = usize::MAX / 4;
+
+pub struct SearchLimits {
+    // The longest sequence of operations to look for
+    pub max_operations: usize,
+    // The most situations to look at before giving up
+    pub max_nodes: usize,
+    // The largest number of tiles a gate can act on
+    pub max_tiles: usize,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        SearchLimits{
+            max_operations: 12,
+            max_nodes: 200_000,
+            max_tiles: SelectionRules::default().max_tiles,
+        }
+    }
+}
+
+// An operation as the player would do it: the gate and the tiles
+// selected for it, in order. A measurement selects one tile of the device.
+#[derive(Clone, PartialEq, Debug, Serialize, Deserialize)]
+pub struct Move {
+    pub gate: Gate,
+    pub tiles: Vec<GridPos>,
+}
+
+impl fmt::Display for Move {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.gate.name())?;
+        for gp in self.tiles.iter() {
+            write!(f, " {}", gp)?;
+        }
+        Ok(())
+    }
+}
+
+// Everything about a level that operations can change
+#[derive(Clone)]
+pub struct Situation {
+    pub state: QState,
+    // Whether the door of each device (in level order) is open
+    pub open_doors: Vec<bool>,
+    pub inventory: GateInventory,
+}
+
+// Situations with the same key behave the same
+type Key = (Vec<(GridPos, i32, i32)>, Vec<bool>, GateInventory);
+
+impl Situation {
+    pub fn initial(level: &Level) -> Self {
+        Situation{
+            state: level.player.clone(),
+            open_doors: vec![false; level.devices.len()],
+            inventory: level.gates.clone(),
+        }
+    }
+
+    fn key(&self) -> Key {
+        /*
+         * The state with its global phase, which cannot be observed,
+         * rotated away using the largest factor, and rounded
+         */
+        let reference = self.state.map.iter()
+            .map(|(gp, factor)| ((factor.norm_sqr() * ROUNDING).round() as i32, *gp, *factor))
+            .max_by(|(norm_a, gp_a, _), (norm_b, gp_b, _)|
+                    norm_a.cmp(norm_b).then(gp_b.cmp(gp_a)))
+            .map(|(_, _, factor)| factor.conj() / factor.norm());
+
+        let mut factors: Vec<(GridPos, i32, i32)> = self.state.map.iter()
+            .map(|(gp, factor)| {
+                let factor = reference.map_or(*factor, |phase| factor * phase);
+                (*gp, (factor.re * ROUNDING).round() as i32, (factor.im * ROUNDING).round() as i32)
+            })
+            .filter(|(_, re, im)| *re != 0 || *im != 0)
+            .collect();
+        factors.sort();
+        (factors, self.open_doors.clone(), self.inventory.clone())
+    }
+
+    fn is_selectable(&self, level: &Level, gp: &GridPos) -> bool {
+        is_free(level, &self.open_doors, gp)
+    }
+
+    pub fn is_solved(&self, level: &Level) -> bool {
+        /*
+         * A level with a goal is solved once the player is there,
+         * a level without one once all doors are open
+         */
+        match level.goal {
+            Some(goal) => goal::reached(&self.state, &goal),
+            None => self.open_doors.iter().all(|open| *open),
+        }
+    }
+
+    fn operations_left(
+        &self,
+        level: &Level,
+        limits: &SearchLimits,
+        distances: &mut Distances,
+        ) -> usize {
+        /*
+         * A lower bound on the operations needed to solve the level.
+         * No gate moves a factor further than the tiles it acts on span,
+         * so without measurements every tile the player is at has to be
+         * walked to the goal. Failed measurements can take away branches,
+         * but leave the doors as they are, so with only those at least
+         * the nearest branch has to be walked there. A successful one
+         * leaves the player at the device, whose tiles have to be reached
+         * first, and opens its door. Then the device's tiles have to be
+         * walked to the goal, or the nearest after more measurements.
+         */
+        let goal = match level.goal {
+            Some(goal) => goal,
+            None => return 0,
+        };
+        let k_gates = [Gate::Cycle, Gate::Fourier, Gate::Diffusion].iter()
+            .any(|gate| self.has_uses(*gate));
+        let step = if k_gates { limits.max_tiles.max(2) - 1 } else { 1 };
+        let cost = |distances: &HashMap<GridPos, usize>, gp: &GridPos| distances.get(gp)
+            .map_or(UNREACHABLE, |distance| (distance + step - 1) / step);
+
+        let to_goal = distances.get(level, &self.open_doors, Target::Goal(goal));
+        let walking = self.state.map.keys()
+            .map(|gp| cost(to_goal, gp))
+            .max()
+            .unwrap_or(0);
+        if !self.has_uses(Gate::Measure) {
+            return walking;
+        }
+
+        let only_failures = 1 + self.state.map.keys()
+            .map(|gp| cost(to_goal, gp))
+            .min()
+            .unwrap_or(UNREACHABLE);
+
+        let to_device = distances.get(level, &self.open_doors, Target::Devices);
+        let reach_device = self.state.map.keys()
+            .map(|gp| cost(to_device, gp))
+            .min()
+            .unwrap_or(UNREACHABLE);
+        let to_goal_open = distances.get(level, &vec![true; level.devices.len()], Target::Goal(goal));
+        let after_success = level.devices.iter()
+            .map(|device| {
+                let costs = device.state.map.keys().map(|gp| cost(to_goal_open, gp));
+                let walking = costs.clone().max().unwrap_or(UNREACHABLE);
+                let nearest = costs.min().unwrap_or(UNREACHABLE);
+                walking.min(1 + nearest)
+            })
+            .min()
+            .unwrap_or(UNREACHABLE);
+
+        walking.min(only_failures).min(reach_device + 1 + after_success)
+    }
+
+    fn has_uses(&self, gate: Gate) -> bool {
+        self.inventory.clone().try_use(gate)
+    }
+
+    pub fn moves(&self, level: &Level, limits: &SearchLimits) -> Vec<Move> {
+        /*
+         * All operations worth trying. Gates have to act on some tile
+         * the player is at, or they would do nothing.
+         */
+        let k_gates: Vec<Gate> = [Gate::Cycle, Gate::Fourier, Gate::Diffusion].iter()
+            .copied()
+            .filter(|gate| self.has_uses(*gate))
+            .collect();
+        let max_tiles = if k_gates.is_empty() { 2 } else { limits.max_tiles };
+        let rules = SelectionRules{ max_tiles };
+
+        // Only tiles close enough to the player can be part of a useful selection
+        let reach = max_tiles as i32 - 1;
+        let mut candidates: Vec<GridPos> = Vec::new();
+        for gp in self.state.map.keys() {
+            for dx in -reach..=reach {
+                for dy in -reach..=reach {
+                    let candidate = GridPos::new(gp.x() + dx, gp.y() + dy);
+                    if !candidates.contains(&candidate) && self.is_selectable(level, &candidate) {
+                        candidates.push(candidate);
+                    }
+                }
+            }
+        }
+        candidates.sort();
+
+        // Every order the tiles can be selected in
+        let mut selections: Vec<Vec<GridPos>> = Vec::new();
+        let mut growing: Vec<Selection> = candidates.iter()
+            .map(|gp| Selection{ tiles: vec![*gp] })
+            .collect();
+        while let Some(selection) = growing.pop() {
+            for gp in candidates.iter() {
+                if selection.can_add(gp, &rules) {
+                    let mut tiles = selection.tiles.clone();
+                    tiles.push(*gp);
+                    growing.push(Selection{ tiles });
+                }
+            }
+            selections.push(selection.tiles);
+        }
+
+        let mut moves = Vec::new();
+        let mut diffused: HashSet<Vec<GridPos>> = HashSet::new();
+        for tiles in selections {
+            if !tiles.iter().any(|gp| self.state.map.contains_key(gp)) || tiles.len() < 2 {
+                continue;
+            }
+            if tiles.len() == 2 {
+                // Switching is symmetric but mixing is not
+                if tiles[0] < tiles[1] && self.has_uses(Gate::Switch) {
+                    moves.push(Move{ gate: Gate::Switch, tiles: tiles.clone() });
+                }
+                if self.has_uses(Gate::Mix) {
+                    moves.push(Move{ gate: Gate::Mix, tiles: tiles.clone() });
+                }
+            }
+            for gate in k_gates.iter() {
+                let redundant = match gate {
+                    // A cycle is the same whichever tile it starts from
+                    Gate::Cycle => tiles.iter().min() != tiles.first(),
+                    // Diffusion does not care about the order at all
+                    Gate::Diffusion => {
+                        let mut sorted = tiles.clone();
+                        sorted.sort();
+                        !diffused.insert(sorted)
+                    }
+                    _ => false,
+                };
+                if !redundant {
+                    moves.push(Move{ gate: *gate, tiles: tiles.clone() });
+                }
+            }
+        }
+
+        // Any selectable tile of a device measures it
+        if self.has_uses(Gate::Measure) {
+            for device in level.devices.iter() {
+                let mut tiles: Vec<&GridPos> = device.state.map.keys()
+                    .filter(|gp| self.is_selectable(level, gp))
+                    .collect();
+                tiles.sort();
+                if let Some(gp) = tiles.first() {
+                    moves.push(Move{ gate: Gate::Measure, tiles: vec![**gp] });
+                }
+            }
+        }
+        moves
+    }
+
+    pub fn apply(&self, level: &Level, mv: &Move) -> Vec<(Situation, f32)> {
+        /*
+         * The situations the move can lead to and how likely they are.
+         * Only measurements have more than one outcome.
+         */
+        let mut next = self.clone();
+        if !next.inventory.try_use(mv.gate) {
+            return Vec::new();
+        }
+        match mv.gate {
+            Gate::Measure => {
+                let index = match level.devices.iter()
+                    .position(|device| device.state.map.contains_key(&mv.tiles[0])) {
+                    Some(index) => index,
+                    None => return Vec::new(),
+                };
+                let success_state = &level.devices[index].state;
+                let prob = self.state.scal_prod(success_state).norm_sqr();
+
+                let mut outcomes = Vec::new();
+                if prob > NEGLIGIBLE_BRANCH {
+                    let mut success = next.clone();
+                    success.state = self.state.collapse_onto(success_state);
+                    success.open_doors[index] = true;
+                    outcomes.push((success, prob));
+                }
+                if 1. - prob > NEGLIGIBLE_BRANCH {
+                    next.state = self.state.collapse_away_from(success_state);
+                    outcomes.push((next, 1. - prob));
+                }
+                return outcomes;
+            }
+            gate => if next.state.apply(gate, &mv.tiles).is_err() {
+                return Vec::new();
+            }
+        }
+        vec![(next, 1.)]
+    }
+}
+
+fn is_free(level: &Level, open_doors: &[bool], gp: &GridPos) -> bool {
+    /*
+     * Same as in the game: the tile has to be on the map
+     * with no wall or closed door on top of it
+     */
+    let on_map = gp.x() >= 0 && gp.y() >= 0
+        && (gp.x() as u32) < level.width && (gp.y() as u32) < level.height;
+    let closed_door = level.devices.iter()
+        .zip(open_doors.iter())
+        .any(|(device, open)| !open && device.door == Some(*gp));
+    on_map && !closed_door && !level.walls.contains(gp)
+}
+
+#[derive(PartialEq, Eq, Hash, Clone, Copy)]
+enum Target {
+    Goal(GridPos),
+    // Any tile of any device
+    Devices,
+}
+
+// Walking distances to the targets, for each combination of open doors
+#[derive(Default)]
+struct Distances {
+    cache: HashMap<(Vec<bool>, Target), HashMap<GridPos, usize>>,
+}
+
+impl Distances {
+    fn get(&mut self, level: &Level, open_doors: &[bool], target: Target) -> &HashMap<GridPos, usize> {
+        self.cache.entry((open_doors.to_vec(), target))
+            .or_insert_with(|| {
+                let from: Vec<GridPos> = match target {
+                    Target::Goal(goal) => vec![goal],
+                    Target::Devices => level.devices.iter()
+                        .flat_map(|device| device.state.map.keys().copied())
+                        .collect(),
+                };
+                walking_distances(level, open_doors, &from)
+            })
+    }
+}
+
+fn walking_distances(
+    level: &Level,
+    open_doors: &[bool],
+    from: &[GridPos],
+    ) -> HashMap<GridPos, usize> {
+    /*
+     * The fewest steps between neighbouring free tiles from the given tiles
+     * to every tile that can be reached
+     */
+    let mut distances: HashMap<GridPos, usize> = from.iter().map(|gp| (*gp, 0)).collect();
+    let mut queue: VecDeque<GridPos> = from.iter().copied().collect();
+    while let Some(gp) = queue.pop_front() {
+        let distance = distances[&gp];
+        for dx in -1..=1 {
+            for dy in -1..=1 {
+                let next = GridPos::new(gp.x() + dx, gp.y() + dy);
+                if is_free(level, open_doors, &next) && !distances.contains_key(&next) {
+                    distances.insert(next, distance + 1);
+                    queue.push_back(next);
+                }
+            }
+        }
+    }
+    distances
+}
+
+pub struct Solution {
+    pub moves: Vec<Move>,
+    // The probability that every measurement on the way turns out as planned
+    pub probability: f32,
+    // The number of situations looked at
+    pub explored: usize,
+}
+
+pub enum SolveError {
+    Unsolvable{ explored: usize },
+    LimitReached{ explored: usize },
+}
+
+impl fmt::Display for SolveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SolveError::Unsolvable{ explored } =>
+                write!(f, "unsolvable ({} states explored)", explored),
+            SolveError::LimitReached{ explored } =>
+                write!(f, "no solution within the search limits ({} states explored)", explored),
+        }
+    }
+}
+
+struct Node {
+    situation: Situation,
+    probability: f32,
+    moves: Vec<Move>,
+}
+
+// How a search with a bound on the number of operations ended
+enum Bounded {
+    Solved(Solution),
+    // Some situations were left out for needing too many operations
+    Pruned,
+    // Every reachable situation was looked at
+    Exhausted,
+}
+
+pub fn solve(level: &Level, limits: &SearchLimits) -> Result<Solution, SolveError> {
+    solve_from(level, Situation::initial(level), limits)
+}
+
+pub fn solve_from(
+    level: &Level,
+    start: Situation,
+    limits: &SearchLimits,
+    ) -> Result<Solution, SolveError> {
+    /*
+     * Looks for the fewest operations that solve the level, allowing
+     * one more operation at a time so that hopeless situations
+     * can be left out early. Among the shortest solutions
+     * the most likely one is returned.
+     */
+    let mut explored = 0;
+    let mut distances = Distances::default();
+    let estimate = start.operations_left(level, limits, &mut distances);
+    // The goal cannot be reached at all, however many operations are allowed
+    if estimate >= UNREACHABLE {
+        return Err(SolveError::Unsolvable{ explored });
+    }
+    for bound in estimate..=limits.max_operations {
+        match search(level, &start, limits, bound, &mut distances, &mut explored)? {
+            Bounded::Solved(solution) => return Ok(solution),
+            Bounded::Exhausted => return Err(SolveError::Unsolvable{ explored }),
+            Bounded::Pruned => (),
+        }
+    }
+    Err(SolveError::LimitReached{ explored })
+}
+
+fn search(
+    level: &Level,
+    start: &Situation,
+    limits: &SearchLimits,
+    bound: usize,
+    distances: &mut Distances,
+    explored: &mut usize,
+    ) -> Result<Bounded, SolveError> {
+    /*
+     * Breadth first search for solutions with at most bound operations.
+     * A situation is only looked at again if it is reached with
+     * a higher probability than before.
+     */
+    if start.is_solved(level) {
+        return Ok(Bounded::Solved(Solution{ moves: Vec::new(), probability: 1., explored: 0 }));
+    }
+    let mut best: HashMap<Key, f32> = HashMap::new();
+    best.insert(start.key(), 1.);
+    let mut frontier = vec![Node{ situation: start.clone(), probability: 1., moves: Vec::new() }];
+    let mut pruned = false;
+    let mut exhausted = false;
+
+    for depth in 1..=bound {
+        let mut next = Vec::new();
+        let mut solution: Option<Solution> = None;
+
+        for node in frontier.iter() {
+            for mv in node.situation.moves(level, limits) {
+                for (situation, prob) in node.situation.apply(level, &mv) {
+                    let probability = node.probability * prob;
+                    if probability < NEGLIGIBLE_BRANCH {
+                        continue;
+                    }
+                    *explored += 1;
+                    if depth + situation.operations_left(level, limits, distances) > bound {
+                        pruned = true;
+                        continue;
+                    }
+                    let key = situation.key();
+                    if best.get(&key).map_or(false, |seen| *seen >= probability - NEGLIGIBLE_BRANCH) {
+                        continue;
+                    }
+                    best.insert(key, probability);
+
+                    let mut moves = node.moves.clone();
+                    moves.push(mv.clone());
+                    if situation.is_solved(level) {
+                        if solution.as_ref().map_or(true, |found| probability > found.probability) {
+                            solution = Some(Solution{ moves, probability, explored: 0 });
+                        }
+                    } else {
+                        next.push(Node{ situation, probability, moves });
+                    }
+                }
+            }
+            if *explored > limits.max_nodes && solution.is_none() {
+                return Err(SolveError::LimitReached{ explored: *explored });
+            }
+        }
+
+        if let Some(solution) = solution {
+            return Ok(Bounded::Solved(Solution{ explored: *explored, ..solution }));
+        }
+        if next.is_empty() {
+            exhausted = true;
+            break;
+        }
+        frontier = next;
+    }
+    if exhausted && !pruned {
+        Ok(Bounded::Exhausted)
+    } else {
+        Ok(Bounded::Pruned)
+    }
+}
+
+pub fn verify_levels(targets: &[String]) -> i32 {
+    /*
+     * Solves the given levels, by name or path to the level file,
+     * or all levels in assets/levels if none are given.
+     * Returns the exit code, which is nonzero if any level could not be solved.
+     */
+    let targets = if targets.is_empty() { level_names() } else { targets.to_vec() };
+    let limits = SearchLimits::default();
+    let mut all_solved = true;
+
+    for target in targets.iter() {
+        let level = if target.ends_with(".ron") {
+            Level::load_path(Path::new(target))
+        } else {
+            Level::load(target)
+        };
+        match level.and_then(|level| solve(&level, &limits).map_err(|e| e.to_string())) {
+            Ok(solution) => {
+                println!("{}: {} operations, success probability {:.3} ({} states explored)",
+                         target, solution.moves.len(), solution.probability, solution.explored);
+                for mv in solution.moves.iter() {
+                    println!("    {}", mv);
+                }
+            }
+            Err(e) => {
+                all_solved = false;
+                println!("{}: {}", target, e);
+            }
+        }
+    }
+    if all_solved { 0 } else { 1 }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::level::DeviceSpec;
+
+    fn level(
+        width: u32,
+        player: &str,
+        goal: Option<(i32, i32)>,
+        devices: &[(&str, Option<(i32, i32)>)],
+        walls: &[(i32, i32)],
+        gates: &[(Gate, Option<u32>)],
+        ) -> Level {
+        /*
+         * A level one tile high
+         */
+        let gp = |(x, y): (i32, i32)| GridPos::new(x, y);
+        Level{
+            width,
+            height: 1,
+            player: player.parse().unwrap(),
+            walls: walls.iter().copied().map(gp).collect(),
+            devices: devices.iter()
+                .map(|(state, door)| DeviceSpec{ state: state.parse().unwrap(), door: door.map(gp) })
+                .collect(),
+            goal: goal.map(gp),
+            gates: GateInventory{ uses: gates.iter().copied().collect() },
+            hints: None,
+        }
+    }
+
+    fn gates(solution: &Solution) -> Vec<Gate> {
+        solution.moves.iter().map(|mv| mv.gate).collect()
+    }
+
+    #[test]
+    fn one_switch() {
+        let level = level(2, "|0,0>", Some((1, 0)), &[], &[], &[(Gate::Switch, None)]);
+        let solution = solve(&level, &SearchLimits::default()).ok().unwrap();
+        assert_eq!(solution.moves, vec![Move{
+            gate: Gate::Switch,
+            tiles: vec![GridPos::new(0, 0), GridPos::new(1, 0)],
+        }]);
+        assert!((solution.probability - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn mix_then_measure() {
+        // Only half of the player can be brought onto the device
+        let level = level(3, "|0,0>", None, &[("|1,0>", Some((2, 0)))], &[],
+                          &[(Gate::Mix, Some(1)), (Gate::Measure, Some(1))]);
+        let solution = solve(&level, &SearchLimits::default()).ok().unwrap();
+        assert_eq!(gates(&solution), vec![Gate::Mix, Gate::Measure]);
+        assert!((solution.probability - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn door_needs_a_measurement() {
+        let level = level(3, "|0,0>", Some((2, 0)), &[("|0,0>", Some((1, 0)))], &[],
+                          &[(Gate::Switch, None), (Gate::Measure, Some(1))]);
+        let solution = solve(&level, &SearchLimits::default()).ok().unwrap();
+        assert_eq!(gates(&solution), vec![Gate::Measure, Gate::Switch, Gate::Switch]);
+        assert!((solution.probability - 1.).abs() < 1e-4);
+    }
+
+    #[test]
+    fn measuring_away_a_far_branch() {
+        // Walking the far branch over would take more operations than
+        // the search allows, and switches could not bring the halves together
+        let level = level(20, "1/sqrt2|0,0> + 1/sqrt2|19,0>", Some((1, 0)),
+                          &[("|19,0>", None)], &[],
+                          &[(Gate::Switch, None), (Gate::Measure, Some(1))]);
+        let solution = solve(&level, &SearchLimits::default()).ok().unwrap();
+        assert_eq!(solution.moves.len(), 2);
+        assert!(gates(&solution).contains(&Gate::Measure));
+        assert!((solution.probability - 0.5).abs() < 1e-4);
+    }
+
+    #[test]
+    fn walled_off_goal_is_unsolvable() {
+        let level = level(3, "|0,0>", Some((2, 0)), &[], &[(1, 0)], &[(Gate::Switch, None)]);
+        assert!(matches!(solve(&level, &SearchLimits::default()),
+                         Err(SolveError::Unsolvable{ .. })));
+    }
+}
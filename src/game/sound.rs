@@ -0,0 +1,256 @@
+use std::collections::HashMap;
+use std::f32::consts::PI;
+use std::time::Duration;
+use bevy::prelude::*;
+use bevy::audio::play_queued_audio_system;
+use bevy::reflect::TypeUuid;
+
+use crate::AppState;
+use crate::actions::Action;
+use super::doors::OpenableByMeasurement;
+use super::operations::{MeasureFailureEvent, MeasureSuccessEvent, MixEvent, SwitchEvent};
+use super::player::{Player, QState};
+use super::selection::SelectTileEvent;
+
+const SAMPLE_RATE: u32 = 44100;
+// Seconds notes take to fade in and out, so that they start and end without a click
+const ATTACK: f32 = 0.01;
+const RELEASE: f32 = 0.03;
+// Cues are played this loud, the sonified state a bit quieter
+const CUE_VOLUME: f32 = 0.3;
+const SONIFICATION_VOLUME: f32 = 0.2;
+// The pitch of positive real factors. The phase raises it through one
+// octave, so opposite phases are a tritone apart and a phase just short
+// of a full turn is just short of the octave, which sounds like the base.
+const SONIFICATION_BASE: f32 = 440.;
+const SONIFICATION_SECONDS: f32 = 0.6;
+
+/*
+ * Plugin
+ */
+// Plays sounds synthesized at startup, as the game has no audio files.
+// They are played through an output of their own, next to bevy's.
+pub struct SoundPlugin;
+
+impl Plugin for SoundPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_asset::<Tone>()
+           .init_non_send_resource::<AudioOutput<Tone>>()
+           .init_resource::<Audio<Tone>>()
+           .init_resource::<SoundCues>()
+           .init_resource::<SoundSettings>()
+           .add_system_to_stage(CoreStage::PostUpdate,
+                                play_queued_audio_system::<Tone>.exclusive_system())
+           .add_system_set(SystemSet::on_update(AppState::InGame)
+                           .with_system(toggle_sound)
+                           .with_system(play_cues)
+                           .with_system(sonify_state));
+    }
+}
+
+/*
+ * Tones
+ */
+// A sound made of sine waves: a sequence of chords, each a list
+// of frequencies with their amplitudes
+#[derive(Clone, TypeUuid)]
+#[uuid = "61f78d43-82bc-4d54-ac10-078389bd3b0e"]
+pub struct Tone {
+    chords: Vec<(f32, Vec<(f32, f32)>)>,
+}
+
+impl Tone {
+    fn notes(notes: &[(f32, f32)]) -> Self {
+        /*
+         * A melody of (frequency, seconds) notes at cue volume
+         */
+        Tone{ chords: notes.iter()
+            .map(|(frequency, seconds)| (*seconds, vec![(*frequency, CUE_VOLUME)]))
+            .collect() }
+    }
+
+    fn chord(frequencies: &[f32], seconds: f32) -> Self {
+        let amplitude = CUE_VOLUME / frequencies.len() as f32;
+        Tone{ chords: vec![(seconds, frequencies.iter().map(|f| (*f, amplitude)).collect())] }
+    }
+}
+
+impl Decodable for Tone {
+    type Decoder = ToneDecoder;
+    type DecoderItem = f32;
+
+    fn decoder(&self) -> Self::Decoder {
+        ToneDecoder{ tone: self.clone(), chord: 0, sample: 0 }
+    }
+}
+
+pub struct ToneDecoder {
+    tone: Tone,
+    chord: usize,
+    // Samples played of the current chord
+    sample: u32,
+}
+
+impl Iterator for ToneDecoder {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        loop {
+            let (seconds, partials) = self.tone.chords.get(self.chord)?;
+            let length = (seconds * SAMPLE_RATE as f32) as u32;
+            if self.sample >= length {
+                self.chord += 1;
+                self.sample = 0;
+                continue;
+            }
+            let t = self.sample as f32 / SAMPLE_RATE as f32;
+            let envelope = (t / ATTACK).min((seconds - t) / RELEASE).min(1.);
+            self.sample += 1;
+            return Some(envelope * partials.iter()
+                .map(|(frequency, amplitude)| amplitude * (2. * PI * frequency * t).sin())
+                .sum::<f32>());
+        }
+    }
+}
+
+impl rodio::Source for ToneDecoder {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        SAMPLE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f32(self.tone.chords.iter().map(|(seconds, _)| seconds).sum()))
+    }
+}
+
+fn sonification(state: &QState) -> Tone {
+    /*
+     * A chord with a note per position: the phase of the factor
+     * is the pitch and its magnitude the volume. Factors in phase
+     * make a single louder note, so interference can be heard.
+     */
+    let mut partials: HashMap<u32, f32> = HashMap::new();
+    for factor in state.map.values() {
+        let phase = factor.arg().rem_euclid(2. * PI);
+        let frequency = SONIFICATION_BASE * 2f32.powf(phase / (2. * PI));
+        *partials.entry(frequency.round() as u32).or_default() += factor.norm();
+    }
+    let total: f32 = partials.values().sum();
+    Tone{ chords: vec![(SONIFICATION_SECONDS, partials.into_iter()
+        .map(|(frequency, magnitude)| (frequency as f32, SONIFICATION_VOLUME * magnitude / total.max(1.)))
+        .collect())] }
+}
+
+/*
+ * Resources
+ */
+pub struct SoundCues {
+    select: Handle<Tone>,
+    switch: Handle<Tone>,
+    mix: Handle<Tone>,
+    success: Handle<Tone>,
+    failure: Handle<Tone>,
+    door: Handle<Tone>,
+}
+
+impl FromWorld for SoundCues {
+    fn from_world(world: &mut World) -> Self {
+        let mut tones = world.get_resource_mut::<Assets<Tone>>().unwrap();
+        SoundCues{
+            select: tones.add(Tone::notes(&[(880., 0.05)])),
+            switch: tones.add(Tone::notes(&[(659., 0.07), (523., 0.07)])),
+            mix: tones.add(Tone::chord(&[440., 554.], 0.2)),
+            success: tones.add(Tone::notes(&[(523., 0.08), (659., 0.08), (784., 0.16)])),
+            failure: tones.add(Tone::notes(&[(220., 0.12), (165., 0.2)])),
+            door: tones.add(Tone::chord(&[110., 165.], 0.35)),
+        }
+    }
+}
+
+pub struct SoundSettings {
+    pub cues: bool,
+    // Whether the player state is played as a chord whenever it changes
+    pub sonify: bool,
+}
+
+impl Default for SoundSettings {
+    fn default() -> Self {
+        SoundSettings{ cues: true, sonify: false }
+    }
+}
+
+/*
+ * Systems
+ */
+pub fn toggle_sound(actions: Res<Input<Action>>, mut settings: ResMut<SoundSettings>) {
+    if actions.just_pressed(Action::ToggleSound) {
+        settings.cues = !settings.cues;
+    }
+    if actions.just_pressed(Action::ToggleSonification) {
+        settings.sonify = !settings.sonify;
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+pub fn play_cues(
+    audio: Res<Audio<Tone>>,
+    cues: Res<SoundCues>,
+    settings: Res<SoundSettings>,
+    mut select_reader: EventReader<SelectTileEvent>,
+    mut switch_reader: EventReader<SwitchEvent>,
+    mut mix_reader: EventReader<MixEvent>,
+    mut success_reader: EventReader<MeasureSuccessEvent>,
+    mut failure_reader: EventReader<MeasureFailureEvent>,
+    opened_door_query: Query<(), (With<OpenableByMeasurement>, Added<Timer>)>,
+    ) {
+    /*
+     * Plays the cue of everything that happened in the frame, once
+     * however often it happened. A door is opening once it is animated.
+     */
+    let cues_due = [
+        (select_reader.iter().count() > 0, &cues.select),
+        (switch_reader.iter().count() > 0, &cues.switch),
+        (mix_reader.iter().count() > 0, &cues.mix),
+        (success_reader.iter().count() > 0, &cues.success),
+        (failure_reader.iter().count() > 0, &cues.failure),
+        (!opened_door_query.is_empty(), &cues.door),
+    ];
+    if !settings.cues {
+        return;
+    }
+    for (due, cue) in cues_due {
+        if due {
+            audio.play(cue.clone());
+        }
+    }
+}
+
+pub fn sonify_state(
+    audio: Res<Audio<Tone>>,
+    settings: Res<SoundSettings>,
+    mut was_on: Local<bool>,
+    mut tones: ResMut<Assets<Tone>>,
+    player_query: Query<(ChangeTrackers<QState>, &QState), With<Player>>,
+    ) {
+    /*
+     * Plays the player state when it changes, or when sonification
+     * is switched on. The tone is dropped once it has been played.
+     */
+    let (tracker, state) = match player_query.get_single() {
+        Ok(player) => player,
+        Err(_) => return,
+    };
+    let switched_on = settings.sonify && !*was_on;
+    *was_on = settings.sonify;
+    if settings.sonify && (tracker.is_changed() || switched_on) {
+        audio.play(tones.add(sonification(state)));
+    }
+}
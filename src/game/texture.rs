@@ -1,20 +1,35 @@
-use bevy::{prelude::*, render::render_resource::TextureUsages};
+use bevy::prelude::*;
+use bevy::render::render_resource::{FilterMode, TextureUsages};
 
+use crate::loading::GameAssets;
+
+pub fn make_pixel_art(image: &mut Image) {
+    /*
+     * Samples the image without smoothing, so that pixel art stays
+     * crisp however far it is scaled. The tilemap copies its textures
+     * into texture arrays, so they have to be copyable too.
+     */
+    image.sampler_descriptor.mag_filter = FilterMode::Nearest;
+    image.sampler_descriptor.min_filter = FilterMode::Nearest;
+    image.sampler_descriptor.mipmap_filter = FilterMode::Nearest;
+    image.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING
+        | TextureUsages::COPY_SRC
+        | TextureUsages::COPY_DST;
+}
+
+/*
+ * Systems
+ */
 pub fn set_texture_filters_to_nearest(
-    mut texture_events: EventReader<AssetEvent<Image>>,
-    mut textures: ResMut<Assets<Image>>,
+    assets: Res<GameAssets>,
+    mut images: ResMut<Assets<Image>>,
 ) {
-    // quick and dirty, run this for all textures anytime a texture is created.
-    for event in texture_events.iter() {
-        match event {
-            AssetEvent::Created { handle } => {
-                if let Some(mut texture) = textures.get_mut(handle) {
-                    texture.texture_descriptor.usage = TextureUsages::TEXTURE_BINDING
-                        | TextureUsages::COPY_SRC
-                        | TextureUsages::COPY_DST;
-                }
-            }
-            _ => (),
+    /*
+     * Makes every preloaded texture pixel art, once they are all loaded
+     */
+    for handle in assets.images() {
+        if let Some(image) = images.get_mut(handle) {
+            make_pixel_art(image);
         }
     }
 }
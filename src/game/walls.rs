@@ -0,0 +1,36 @@
+use bevy::prelude::*;
+
+use crate::loading::GameAssets;
+use super::coords::*;
+use super::operations::Blocking;
+
+#[derive(Component)]
+pub struct Wall;
+
+pub fn spawn_walls(
+    commands: &mut Commands,
+    assets: &GameAssets,
+    grid: &GridConfig,
+    walls: &[GridPos],
+    ) {
+    /*
+     * Spawns the walls, which block the tiles they are on for good
+     */
+    for gp in walls.iter() {
+        let world_pos = grid.grid_to_world(gp);
+        commands.spawn_bundle(SpriteSheetBundle{
+            texture_atlas: assets.walls.clone(),
+            sprite: TextureAtlasSprite{
+                // The dark grey stone tile, scaled up to the grid
+                index: 4,
+                custom_size: Some(grid.tile()),
+                ..Default::default()
+            },
+            transform: Transform::from_xyz(world_pos.x, world_pos.y, 1.),
+            ..Default::default()
+        })
+        .insert(Wall)
+        .insert(Blocking)
+        .insert(*gp);
+    }
+}
@@ -0,0 +1,144 @@
+use std::collections::{HashMap, HashSet};
+use bevy::prelude::*;
+use bevy::render::render_resource::{Extent3d, TextureDimension, TextureFormat};
+use bevy_ecs_tilemap::prelude::*;
+use num::complex;
+
+use crate::actions::Action;
+use super::coords::*;
+use super::animation::DisplayedState;
+use super::player::{Player, QState};
+use super::scheme::Scheme;
+use super::texture::make_pixel_art;
+#[allow(non_camel_case_types)]
+type c32 = complex::Complex32;
+
+// Layers are drawn at the height of their id, so this one goes
+// above walls and devices and below the superposition sprites
+pub const OVERLAY_LAYER: u16 = 5;
+// States spread over more positions than this are always shown
+// by the overlay, as a sprite per position would be too slow
+const MAX_SPRITES: usize = 64;
+
+/*
+ * Resources
+ */
+// Whether the player state is drawn by tinting tiles instead of with sprites
+#[derive(Default)]
+pub struct WaveOverlay {
+    pub enabled: bool,
+}
+
+impl WaveOverlay {
+    pub fn shows(&self, state: &QState) -> bool {
+        self.enabled || state.map.len() > MAX_SPRITES
+    }
+}
+
+// The factors the overlay tiles are tinted after, so that only
+// the tiles whose factor changes are retinted. Reset with the level.
+#[derive(Default)]
+pub struct TintedTiles {
+    factors: HashMap<GridPos, c32>,
+}
+
+pub fn build_overlay_layer(
+    commands: &mut Commands,
+    images: &mut ResMut<Assets<Image>>,
+    map_query: &mut MapQuery,
+    map: &mut Map,
+    layer_settings: LayerSettings,
+    width: u32,
+    height: u32,
+    ) {
+    /*
+     * Builds the overlay layer over the whole level, with a plain white
+     * texture for the tiles to be tinted. The tiles start out clear.
+     */
+    let mut texture = Image::new_fill(
+        Extent3d{
+            width: layer_settings.tile_size.0 as u32,
+            height: layer_settings.tile_size.1 as u32,
+            depth_or_array_layers: 1,
+        },
+        TextureDimension::D2,
+        &[255, 255, 255, 255],
+        TextureFormat::Rgba8UnormSrgb,
+    );
+    let (mut layer_builder, _) = LayerBuilder::new(
+        commands,
+        layer_settings,
+        0u16,
+        OVERLAY_LAYER,
+    );
+    layer_builder.fill(TilePos(0, 0), TilePos(width, height), TileBundle {
+        tile: Tile{ color: Color::NONE, ..Default::default() },
+        ..Default::default()
+    });
+    make_pixel_art(&mut texture);
+    let layer_entity = map_query.build_layer(commands, layer_builder, images.add(texture));
+    map.add_layer(commands, OVERLAY_LAYER, layer_entity);
+}
+
+/*
+ * Systems
+ */
+pub fn toggle_wave_overlay(actions: Res<Input<Action>>, mut overlay: ResMut<WaveOverlay>) {
+    if actions.just_pressed(Action::ToggleWaveOverlay) {
+        overlay.enabled = !overlay.enabled;
+    }
+}
+
+pub fn update_wave_overlay(
+    overlay: Res<WaveOverlay>,
+    scheme: Res<Scheme>,
+    mut tinted: ResMut<TintedTiles>,
+    player_query: Query<&DisplayedState, With<Player>>,
+    changed_query: Query<Entity, (Changed<DisplayedState>, With<Player>)>,
+    mut tile_query: Query<(&mut Tile, &TileParent)>,
+    mut map_query: MapQuery,
+    ) {
+    /*
+     * Retints the overlay tiles whose factor differs from the one
+     * they show, whenever the displayed state changes or the overlay is switched.
+     * A new color scheme retints every tile. Each chunk with a retinted
+     * tile is remeshed once.
+     */
+    if !overlay.is_changed() && !scheme.is_changed() && changed_query.is_empty() {
+        return;
+    }
+    let state = match player_query.get_single() {
+        Ok(DisplayedState(state)) => state,
+        Err(_) => return,
+    };
+    let empty = HashMap::new();
+    let wanted = if overlay.shows(state) { &state.map } else { &empty };
+    let changed: HashSet<GridPos> = tinted.factors.keys()
+        .chain(wanted.keys())
+        .filter(|gp| scheme.is_changed() || tinted.factors.get(gp) != wanted.get(gp))
+        .copied()
+        .collect();
+
+    let mut chunks = HashSet::new();
+    for gp in changed {
+        if gp.x() < 0 || gp.y() < 0 {
+            continue;
+        }
+        let tile_pos = TilePos(gp.x() as u32, gp.y() as u32);
+        let tile = map_query.get_tile_entity(tile_pos, 0u16, OVERLAY_LAYER)
+            .ok()
+            .and_then(|tile_entity| tile_query.get_mut(tile_entity).ok());
+        if let Some((mut tile, parent)) = tile {
+            let factor = wanted.get(&gp).copied();
+            tile.color = factor.map_or(Color::NONE, |factor| scheme.phase_color(factor));
+            chunks.insert(parent.chunk);
+            match factor {
+                Some(factor) => tinted.factors.insert(gp, factor),
+                None => tinted.factors.remove(&gp),
+            };
+        }
+    }
+    for chunk in chunks {
+        map_query.notify_chunk(chunk);
+    }
+}
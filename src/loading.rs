@@ -0,0 +1,189 @@
+use bevy::prelude::*;
+use bevy::asset::LoadState;
+
+use crate::AppState;
+
+// Everything the game draws with, loaded once up front
+const SPRITES: [&str; 7] = [
+    "sprites/player_front.png",
+    "sprites/bar.png",
+    "sprites/arrow.png",
+    "sprites/green_arrow.png",
+    "sprites/measuring_device.png",
+    "sprites/select.png",
+    "sprites/grass_tile.png",
+];
+// Sprite sheets with the size of a frame and their columns and rows
+const ATLASES: [(&str, f32, usize, usize); 3] = [
+    ("sprites/flower_sheet.png", 32.0, 1, 14),
+    ("sprites/tiles.png", 16.0, 6, 1),
+    ("sprites/door_anim.png", 64.0, 10, 1),
+];
+const FONTS: [&str; 2] = [
+    "fonts/Evolventa.ttf",
+    "fonts/EvolventaBold.ttf",
+];
+const BAR_WIDTH: f32 = 400.0;
+
+pub struct LoadingPlugin;
+
+impl Plugin for LoadingPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_system_set(SystemSet::on_enter(AppState::Loading)
+                           .with_system(start_loading))
+            .add_system_set(SystemSet::on_update(AppState::Loading)
+                            .with_system(check_loading))
+            .add_system_set(SystemSet::on_exit(AppState::Loading)
+                            .with_system(teardown));
+    }
+}
+
+/*
+ * Components
+ */
+#[derive(Component)]
+struct LoadingScreen;
+#[derive(Component)]
+struct ProgressBar;
+
+/*
+ * Resources
+ */
+// The handles of all the game's assets, which spawning
+// uses instead of loading by path
+pub struct GameAssets {
+    pub player: Handle<Image>,
+    pub bar: Handle<Image>,
+    pub arrow: Handle<Image>,
+    pub green_arrow: Handle<Image>,
+    pub measuring_device: Handle<Image>,
+    pub select: Handle<Image>,
+    pub grass_tile: Handle<Image>,
+    pub flower: Handle<TextureAtlas>,
+    pub walls: Handle<TextureAtlas>,
+    pub door: Handle<TextureAtlas>,
+    pub font: Handle<Font>,
+    pub bold_font: Handle<Font>,
+    // The sprites and the sheets behind the atlases
+    images: Vec<Handle<Image>>,
+    // Every file above, to tell how far loading is
+    files: Vec<HandleUntyped>,
+}
+
+impl GameAssets {
+    fn load(asset_server: &AssetServer, texture_atlases: &mut Assets<TextureAtlas>) -> Self {
+        /*
+         * Starts loading every file of the manifest. The sprite sheets
+         * are cut up right away, as that needs nothing but their grid.
+         */
+        let sprites: Vec<Handle<Image>> = SPRITES.iter().map(|path| asset_server.load(*path)).collect();
+        let sheets: Vec<Handle<Image>> = ATLASES.iter().map(|(path, ..)| asset_server.load(*path)).collect();
+        let atlases: Vec<Handle<TextureAtlas>> = ATLASES.iter()
+            .zip(sheets.iter())
+            .map(|((_, size, columns, rows), sheet)| texture_atlases.add(
+                TextureAtlas::from_grid(sheet.clone(), Vec2::new(*size, *size), *columns, *rows)))
+            .collect();
+        let fonts: Vec<Handle<Font>> = FONTS.iter().map(|path| asset_server.load(*path)).collect();
+
+        let images: Vec<Handle<Image>> = sprites.iter().chain(sheets.iter()).cloned().collect();
+        let files = images.iter().map(|handle| handle.clone_untyped())
+            .chain(fonts.iter().map(|handle| handle.clone_untyped()))
+            .collect();
+        GameAssets{
+            player: sprites[0].clone(),
+            bar: sprites[1].clone(),
+            arrow: sprites[2].clone(),
+            green_arrow: sprites[3].clone(),
+            measuring_device: sprites[4].clone(),
+            select: sprites[5].clone(),
+            grass_tile: sprites[6].clone(),
+            flower: atlases[0].clone(),
+            walls: atlases[1].clone(),
+            door: atlases[2].clone(),
+            font: fonts[0].clone(),
+            bold_font: fonts[1].clone(),
+            images,
+            files,
+        }
+    }
+
+    pub fn images(&self) -> impl Iterator<Item = &Handle<Image>> {
+        self.images.iter()
+    }
+}
+
+/*
+ * Systems
+ */
+fn start_loading(
+    mut commands: Commands,
+    asset_server: Res<AssetServer>,
+    mut texture_atlases: ResMut<Assets<TextureAtlas>>,
+    ) {
+    /*
+     * Starts loading the assets and shows an empty progress bar
+     */
+    commands.insert_resource(GameAssets::load(&asset_server, &mut texture_atlases));
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Px(BAR_WIDTH), Val::Px(24.0)),
+                // center the bar
+                margin: Rect::all(Val::Auto),
+                ..Default::default()
+            },
+            color: Color::rgb(0.15, 0.15, 0.15).into(),
+            ..Default::default()
+        })
+        .insert(LoadingScreen)
+        .with_children(|parent| {
+            parent.spawn_bundle(NodeBundle {
+                style: Style {
+                    size: Size::new(Val::Px(0.0), Val::Percent(100.0)),
+                    ..Default::default()
+                },
+                color: Color::rgb(0.35, 0.75, 0.35).into(),
+                ..Default::default()
+            })
+            .insert(ProgressBar);
+        });
+}
+
+fn check_loading(
+    asset_server: Res<AssetServer>,
+    assets: Res<GameAssets>,
+    mut state: ResMut<State<AppState>>,
+    mut bar_query: Query<&mut Style, With<ProgressBar>>,
+    ) {
+    /*
+     * Fills the bar as the files come in and starts the game once
+     * all of them are in. Files that fail to load are left out,
+     * so that the game still starts.
+     */
+    let states: Vec<LoadState> = assets.files.iter()
+        .map(|handle| asset_server.get_load_state(handle))
+        .collect();
+    let done = states.iter()
+        .filter(|load_state| matches!(load_state, LoadState::Loaded | LoadState::Failed))
+        .count();
+    for mut style in bar_query.iter_mut() {
+        style.size.width = Val::Px(BAR_WIDTH * done as f32 / states.len() as f32);
+    }
+    if done == states.len() {
+        for (handle, load_state) in assets.files.iter().zip(states.iter()) {
+            if *load_state == LoadState::Failed {
+                if let Some(path) = asset_server.get_handle_path(handle) {
+                    error!("Could not load {:?}", path.path());
+                }
+            }
+        }
+        state.set(AppState::Reloading).unwrap();
+    }
+}
+
+fn teardown(mut commands: Commands, screen_query: Query<Entity, With<LoadingScreen>>) {
+    for entity in screen_query.iter() {
+        commands.entity(entity).despawn_recursive();
+    }
+}
@@ -1,26 +1,85 @@
 use bevy::prelude::*;
-use bevy::window::WindowMode;
+use bevy::log::{Level, LogSettings};
+use std::path::Path;
 
+mod actions;
+mod config;
+mod settings;
+mod loading;
 mod menu;
 mod game;
 
 fn main() {
-    App::new()
-        .insert_resource(WindowDescriptor {
-            title: "Quantum Adventure".to_string(),
-            mode: WindowMode::BorderlessFullscreen,
-            ..Default::default()
+    // `--solve [levels]` checks that the levels can be solved and
+    // `--generate [code]` prints a generated level, instead of playing.
+    // `--replay <file>` plays a recorded run back.
+    // Any other arguments override the settings for this run.
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let mut replay = None;
+    let mut overrides = &args[..];
+    match args.first().map(String::as_str) {
+        Some("--solve") => std::process::exit(game::solver::verify_levels(&args[1..])),
+        Some("--generate") => std::process::exit(game::generator::print_level(&args[1..])),
+        Some("--replay") => {
+            let loaded = args.get(1)
+                .ok_or_else(|| "--replay needs a replay file".to_string())
+                .and_then(|path| game::replay::Replay::load(Path::new(path)));
+            match loaded {
+                Ok(loaded) => replay = Some(loaded),
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            }
+            overrides = &args[2..];
+        }
+        _ => (),
+    }
+    let mut settings = settings::Settings::load();
+    if let Err(e) = settings.apply_args(overrides) {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+
+    let mut app = App::new();
+    app
+        .insert_resource(settings.window())
+        .insert_resource(game::CurrentLevel{ name: settings.level() })
+        .insert_resource(settings)
+        // All of the game's own messages get through the filter,
+        // the Verbosity resource decides which of them are logged
+        .insert_resource(LogSettings {
+            filter: "wgpu=error,quantum_adventure_game=trace".to_string(),
+            level: Level::INFO,
         })
         .add_plugins(DefaultPlugins)
-        .add_state(AppState::InGame)
+        .add_plugin(actions::ActionsPlugin)
+        .add_state(AppState::Loading)
+        .add_startup_system(spawn_ui_camera)
+        .add_plugin(loading::LoadingPlugin)
         .add_plugin(menu::MenuPlugin)
-        .add_plugin(game::GamePlugin)
-        .run();
+        .add_plugin(game::GamePlugin);
+    if let Some(replay) = replay {
+        app.insert_resource(game::CurrentLevel{ name: replay.level.clone() })
+           .insert_resource(game::replay::Playback::new(replay));
+    }
+    app.run();
 }
 
 #[derive(PartialEq, Eq, Hash, Clone, Copy, Debug)]
 enum AppState {
+    // Preloads the assets, then loads the level
+    Loading,
     MainMenu,
+    Settings,
+    Controls,
     InGame,
-    Paused,
+    // Reads or generates the level, then goes in game. Passed
+    // through to set the game up again, e.g. for another level
+    Reloading,
+}
+
+// The ui camera is shared by all states
+fn spawn_ui_camera(mut commands: Commands) {
+    commands.spawn_bundle(UiCameraBundle::default());
 }
@@ -2,6 +2,11 @@ use bevy::prelude::*;
 use bevy::app::AppExit;
 
 use crate::AppState;
+use crate::loading::GameAssets;
+use crate::game::{CurrentLevel, generator::LevelCode};
+
+mod controls;
+mod settings;
 
 pub struct MenuPlugin;
 
@@ -12,6 +17,24 @@ impl Plugin for MenuPlugin {
             .add_system_set(SystemSet::on_update(AppState::MainMenu)
                             .with_system(button_system))
             .add_system_set(SystemSet::on_exit(AppState::MainMenu)
+                            .with_system(teardown))
+            .init_resource::<controls::AwaitingBinding>()
+            .add_system_set(SystemSet::on_enter(AppState::Controls)
+                            .with_system(controls::setup))
+            .add_system_set(SystemSet::on_update(AppState::Controls)
+                            .with_system(controls::button_system)
+                            .with_system(controls::capture_binding)
+                            .with_system(controls::update_binding_labels))
+            .add_system_set(SystemSet::on_exit(AppState::Controls)
+                            .with_system(controls::save)
+                            .with_system(teardown))
+            .add_system_set(SystemSet::on_enter(AppState::Settings)
+                            .with_system(settings::setup))
+            .add_system_set(SystemSet::on_update(AppState::Settings)
+                            .with_system(settings::button_system)
+                            .with_system(settings::update_labels))
+            .add_system_set(SystemSet::on_exit(AppState::Settings)
+                            .with_system(settings::save)
                             .with_system(teardown));
     }
 }
@@ -23,16 +46,21 @@ const PRESSED_BUTTON: Color = Color::rgb(0.35, 0.75, 0.35);
 #[derive(Component)]
 enum ButtonAction {
     Play,
+    Practice,
+    Controls,
+    Settings,
     Quit,
 }
 
+#[allow(clippy::type_complexity)]
 fn button_system(
     mut interaction_query: Query<
         (&Interaction, &mut UiColor, &mut Style, &ButtonAction),
         (Changed<Interaction>, With<Button>),
     >,
     mut exit: EventWriter<AppExit>,
-    mut state: ResMut<State<AppState>>
+    mut state: ResMut<State<AppState>>,
+    mut current_level: ResMut<CurrentLevel>,
 ) {
     for (interaction, mut color, mut style, action) in interaction_query.iter_mut() {
         match *interaction {
@@ -40,7 +68,14 @@ fn button_system(
                 *color = PRESSED_BUTTON.into();
                 match *action {
                     ButtonAction::Quit => exit.send(AppExit),
-                    ButtonAction::Play => state.set(AppState::InGame).unwrap(),
+                    ButtonAction::Play => state.set(AppState::Reloading).unwrap(),
+                    ButtonAction::Practice => {
+                        // A fresh generated level every time
+                        current_level.name = LevelCode::default().to_string();
+                        state.set(AppState::Reloading).unwrap();
+                    }
+                    ButtonAction::Controls => state.set(AppState::Controls).unwrap(),
+                    ButtonAction::Settings => state.set(AppState::Settings).unwrap(),
                 };
             }
             Interaction::Hovered => {
@@ -55,71 +90,82 @@ fn button_system(
     }
 }
 
-fn setup(mut commands: Commands, asset_server: Res<AssetServer>) {
-    // ui camera
-    commands.spawn_bundle(UiCameraBundle::default());
+fn setup(mut commands: Commands, assets: Res<GameAssets>) {
+    let text_style = TextStyle {
+        font: assets.font.clone(),
+        font_size: 40.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+    let buttons = [
+        ("Play", ButtonAction::Play),
+        ("Practice", ButtonAction::Practice),
+        ("Controls", ButtonAction::Controls),
+        ("Settings", ButtonAction::Settings),
+        ("Quit", ButtonAction::Quit),
+    ];
+
     commands
-        .spawn_bundle(ButtonBundle {
+        .spawn_bundle(NodeBundle {
             style: Style {
-                size: Size::new(Val::Px(150.0), Val::Px(65.0)),
-                // center button
-                margin: Rect::all(Val::Auto),
-                // horizontally center child text
-                justify_content: JustifyContent::Center,
-                // vertically center child text
-                align_items: AlignItems::Center,
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
                 ..Default::default()
             },
-            color: NORMAL_BUTTON.into(),
+            color: Color::NONE.into(),
             ..Default::default()
         })
         .with_children(|parent| {
-            parent.spawn_bundle(TextBundle {
-                text: Text::with_section(
-                    "Play",
-                    TextStyle {
-                        font: asset_server.load("fonts/Evolventa.ttf"),
-                        font_size: 40.0,
-                        color: Color::rgb(0.9, 0.9, 0.9),
-                    },
-                    Default::default(),
-                ),
-                ..Default::default()
-            });
-        })
-        .insert(ButtonAction::Play);
+            for (label, action) in buttons {
+                spawn_button(parent, label, main_button(), &text_style, action);
+            }
+        });
+}
 
-    // Quit button
-    commands
+fn main_button() -> Style {
+    Style {
+        size: Size::new(Val::Px(150.0), Val::Px(65.0)),
+        // center button
+        margin: Rect::all(Val::Auto),
+        // horizontally center child text
+        justify_content: JustifyContent::Center,
+        // vertically center child text
+        align_items: AlignItems::Center,
+        ..Default::default()
+    }
+}
+
+// The buttons listed in the controls and settings screens
+fn list_button() -> Style {
+    Style {
+        size: Size::new(Val::Px(250.0), Val::Px(32.0)),
+        margin: Rect::all(Val::Px(2.0)),
+        // horizontally center child text
+        justify_content: JustifyContent::Center,
+        // vertically center child text
+        align_items: AlignItems::Center,
+        ..Default::default()
+    }
+}
+
+fn spawn_button(
+    parent: &mut ChildBuilder,
+    label: &str,
+    style: Style,
+    text_style: &TextStyle,
+    button: impl Component,
+) {
+    parent
         .spawn_bundle(ButtonBundle {
-            style: Style {
-                size: Size::new(Val::Px(150.0), Val::Px(65.0)),
-                // center button
-                margin: Rect::all(Val::Auto),
-                // horizontally center child text
-                justify_content: JustifyContent::Center,
-                // vertically center child text
-                align_items: AlignItems::Center,
-                ..Default::default()
-            },
+            style,
             color: NORMAL_BUTTON.into(),
             ..Default::default()
         })
         .with_children(|parent| {
             parent.spawn_bundle(TextBundle {
-                text: Text::with_section(
-                    "Quit",
-                    TextStyle {
-                        font: asset_server.load("fonts/Evolventa.ttf"),
-                        font_size: 40.0,
-                        color: Color::rgb(0.9, 0.9, 0.9),
-                    },
-                    Default::default(),
-                ),
+                text: Text::with_section(label, text_style.clone(), Default::default()),
                 ..Default::default()
             });
         })
-        .insert(ButtonAction::Quit);
+        .insert(button);
 }
 
 // remove all entities that are not a camera
@@ -0,0 +1,258 @@
+use bevy::prelude::*;
+
+use crate::AppState;
+use crate::loading::GameAssets;
+use crate::actions::{Action, Binding, Bindings};
+use super::{NORMAL_BUTTON, HOVERED_BUTTON, PRESSED_BUTTON, list_button, spawn_button};
+
+// Seconds to press the new binding in, after which the rebinding is cancelled
+const CAPTURE_SECONDS: f32 = 5.;
+
+/*
+ * Components
+ */
+#[derive(Component)]
+pub enum ControlsButton {
+    Rebind(Action),
+    Reset,
+    Back,
+}
+
+/*
+ * Resources
+ */
+#[derive(Default)]
+pub struct AwaitingBinding {
+    // The action whose binding is being changed, if any
+    action: Option<Action>,
+    // Becomes true once the click that started the rebinding is released,
+    // so that the click itself is not taken as the new binding
+    armed: bool,
+    // Runs out when nothing was pressed in time. There is no key to cancel
+    // with, as every key can be bound, Escape included.
+    timer: Timer,
+}
+
+impl AwaitingBinding {
+    fn new(action: Action) -> Self {
+        AwaitingBinding{
+            action: Some(action),
+            armed: false,
+            timer: Timer::from_seconds(CAPTURE_SECONDS, false),
+        }
+    }
+}
+
+/*
+ * Systems
+ */
+#[allow(clippy::type_complexity)]
+pub fn button_system(
+    mut interaction_query: Query<
+        (&Interaction, &mut UiColor, &ControlsButton),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut bindings: ResMut<Bindings>,
+    mut awaiting: ResMut<AwaitingBinding>,
+    mut state: ResMut<State<AppState>>
+) {
+    for (interaction, mut color, button) in interaction_query.iter_mut() {
+        match *interaction {
+            Interaction::Clicked => {
+                *color = PRESSED_BUTTON.into();
+                match *button {
+                    ControlsButton::Rebind(action) => {
+                        *awaiting = AwaitingBinding::new(action);
+                    }
+                    ControlsButton::Reset => *bindings = Bindings::default(),
+                    ControlsButton::Back => state.set(AppState::MainMenu).unwrap(),
+                };
+            }
+            Interaction::Hovered => *color = HOVERED_BUTTON.into(),
+            Interaction::None => *color = NORMAL_BUTTON.into(),
+        }
+    }
+}
+
+pub fn capture_binding(
+    time: Res<Time>,
+    keys: Res<Input<KeyCode>>,
+    mouse_buttons: Res<Input<MouseButton>>,
+    gamepad_buttons: Res<Input<GamepadButton>>,
+    mut bindings: ResMut<Bindings>,
+    mut awaiting: ResMut<AwaitingBinding>,
+) {
+    /*
+     * Binds the next key, mouse or gamepad button pressed to the awaiting action,
+     * unless none is pressed in time
+     */
+    let action = if let Some(action) = awaiting.action { action } else { return; };
+
+    if awaiting.timer.tick(time.delta()).finished() {
+        *awaiting = AwaitingBinding::default();
+        return;
+    }
+
+    if !awaiting.armed {
+        if mouse_buttons.get_pressed().len() == 0 {
+            awaiting.armed = true;
+        }
+        return;
+    }
+
+    let binding = if let Some(key) = keys.get_just_pressed().next() {
+        Binding::Key(*key)
+    } else if let Some(button) = mouse_buttons.get_just_pressed().next() {
+        Binding::Mouse(*button)
+    } else if let Some(button) = gamepad_buttons.get_just_pressed().next() {
+        Binding::Gamepad(button.1)
+    } else {
+        return;
+    };
+
+    for other in bindings.rebind(action, binding) {
+        warn!("{} is also bound to {}", binding.name(), other.name());
+    }
+    *awaiting = AwaitingBinding::default();
+}
+
+pub fn update_binding_labels(
+    bindings: Res<Bindings>,
+    awaiting: Res<AwaitingBinding>,
+    button_query: Query<(&ControlsButton, &Children)>,
+    mut text_query: Query<&mut Text>,
+) {
+    if !bindings.is_changed() && !awaiting.is_changed() {
+        return;
+    }
+    for (button, children) in button_query.iter() {
+        let action = if let ControlsButton::Rebind(action) = button {
+            *action
+        } else { continue; };
+
+        let label = if awaiting.action == Some(action) {
+            // with the seconds left
+            let left = awaiting.timer.duration().as_secs_f32() - awaiting.timer.elapsed_secs();
+            format!("Press a button... {}", left.ceil())
+        } else {
+            bindings.get(action)
+                .iter()
+                .map(Binding::name)
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        for &child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(child) {
+                text.sections[0].value = label.clone();
+            }
+        }
+    }
+}
+
+pub fn save(bindings: Res<Bindings>, mut awaiting: ResMut<AwaitingBinding>) {
+    bindings.save();
+    *awaiting = AwaitingBinding::default();
+}
+
+pub fn setup(mut commands: Commands, assets: Res<GameAssets>, bindings: Res<Bindings>) {
+    let font = assets.font.clone();
+    let text_style = TextStyle {
+        font,
+        font_size: 24.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                // list the actions from the top
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            // The actions are split in two columns so that they fit on the screen
+            parent
+                .spawn_bundle(NodeBundle {
+                    style: Style {
+                        // line the columns up at the top
+                        align_items: AlignItems::FlexEnd,
+                        ..Default::default()
+                    },
+                    color: Color::NONE.into(),
+                    ..Default::default()
+                })
+                .with_children(|parent| {
+                    for column in Action::ALL.chunks((Action::ALL.len() + 1) / 2) {
+                        parent
+                            .spawn_bundle(NodeBundle {
+                                style: Style {
+                                    flex_direction: FlexDirection::ColumnReverse,
+                                    margin: Rect::all(Val::Px(10.0)),
+                                    ..Default::default()
+                                },
+                                color: Color::NONE.into(),
+                                ..Default::default()
+                            })
+                            .with_children(|parent| {
+                                for &action in column {
+                                    spawn_binding_row(parent, action, &bindings, &text_style);
+                                }
+                            });
+                    }
+                });
+            parent
+                .spawn_bundle(NodeBundle {
+                    color: Color::NONE.into(),
+                    ..Default::default()
+                })
+                .with_children(|row| {
+                    spawn_button(row, "Reset", list_button(), &text_style, ControlsButton::Reset);
+                    spawn_button(row, "Back", list_button(), &text_style, ControlsButton::Back);
+                });
+        });
+}
+
+fn spawn_binding_row(
+    parent: &mut ChildBuilder,
+    action: Action,
+    bindings: &Bindings,
+    text_style: &TextStyle,
+) {
+    /*
+     * The name of the action and a button showing its bindings
+     */
+    let label = bindings.get(action)
+        .iter()
+        .map(Binding::name)
+        .collect::<Vec<_>>()
+        .join(", ");
+    parent
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|row| {
+            // Action name
+            row.spawn_bundle(TextBundle {
+                style: Style {
+                    size: Size::new(Val::Px(250.0), Val::Auto),
+                    ..Default::default()
+                },
+                text: Text::with_section(
+                    action.name(), text_style.clone(), Default::default()),
+                ..Default::default()
+            });
+            // Binding button
+            spawn_button(row, &label, list_button(), text_style, ControlsButton::Rebind(action));
+        });
+}
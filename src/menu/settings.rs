@@ -0,0 +1,218 @@
+use bevy::prelude::*;
+
+use crate::AppState;
+use crate::loading::GameAssets;
+use crate::game::{CurrentLevel, GameRng, level_names};
+use crate::settings::{Settings, RESOLUTIONS, TILE_SIZES};
+use super::{NORMAL_BUTTON, HOVERED_BUTTON, PRESSED_BUTTON, list_button, spawn_button};
+
+/*
+ * Components
+ */
+#[derive(Component, Clone, Copy)]
+pub enum SettingsButton {
+    Fullscreen,
+    Resolution,
+    Vsync,
+    TileSize,
+    Level,
+    Seed,
+    Back,
+}
+
+impl SettingsButton {
+    const OPTIONS: [SettingsButton; 6] = [
+        SettingsButton::Fullscreen,
+        SettingsButton::Resolution,
+        SettingsButton::Vsync,
+        SettingsButton::TileSize,
+        SettingsButton::Level,
+        SettingsButton::Seed,
+    ];
+
+    fn name(self) -> &'static str {
+        match self {
+            SettingsButton::Fullscreen => "Fullscreen",
+            SettingsButton::Resolution => "Window size",
+            SettingsButton::Vsync => "VSync",
+            SettingsButton::TileSize => "Tile size",
+            SettingsButton::Level => "First level",
+            SettingsButton::Seed => "Measurement seed",
+            SettingsButton::Back => "Back",
+        }
+    }
+
+    fn label(self, settings: &Settings) -> String {
+        /*
+         * The setting in effect, which the button changes
+         */
+        let on_off = |on: bool| if on { "On" } else { "Off" }.to_string();
+        match self {
+            SettingsButton::Fullscreen => on_off(settings.fullscreen()),
+            SettingsButton::Resolution => {
+                let (width, height) = settings.resolution();
+                format!("{}x{}", width, height)
+            }
+            SettingsButton::Vsync => on_off(settings.vsync()),
+            SettingsButton::TileSize => format!("{} px", settings.tile_size()),
+            SettingsButton::Level => settings.level(),
+            SettingsButton::Seed => settings.seed()
+                .map_or("Random".to_string(), |seed| seed.to_string()),
+            SettingsButton::Back => "Back".to_string(),
+        }
+    }
+}
+
+/*
+ * Systems
+ */
+#[allow(clippy::type_complexity)]
+pub fn button_system(
+    mut interaction_query: Query<
+        (&Interaction, &mut UiColor, &SettingsButton),
+        (Changed<Interaction>, With<Button>),
+    >,
+    mut settings: ResMut<Settings>,
+    mut windows: ResMut<Windows>,
+    mut current_level: ResMut<CurrentLevel>,
+    rng: Res<GameRng>,
+    mut state: ResMut<State<AppState>>
+) {
+    /*
+     * Changes the setting of the clicked button, applying it at once.
+     * A setting changed here no longer takes the command line's.
+     */
+    for (interaction, mut color, button) in interaction_query.iter_mut() {
+        match *interaction {
+            Interaction::Clicked => {
+                *color = PRESSED_BUTTON.into();
+                match *button {
+                    SettingsButton::Fullscreen => {
+                        settings.fullscreen = !settings.fullscreen();
+                        settings.overrides.fullscreen = None;
+                    }
+                    SettingsButton::Resolution => {
+                        let next = RESOLUTIONS.iter()
+                            .position(|size| *size == settings.resolution())
+                            .map_or(0, |i| (i + 1) % RESOLUTIONS.len());
+                        settings.resolution = RESOLUTIONS[next];
+                        settings.overrides.resolution = None;
+                    }
+                    SettingsButton::Vsync => {
+                        settings.vsync = !settings.vsync();
+                        settings.overrides.vsync = None;
+                    }
+                    // Takes effect from the next level on
+                    SettingsButton::TileSize => {
+                        let next = TILE_SIZES.iter()
+                            .position(|size| *size == settings.tile_size())
+                            .map_or(0, |i| (i + 1) % TILE_SIZES.len());
+                        settings.tile_size = TILE_SIZES[next];
+                        settings.overrides.tile_size = None;
+                    }
+                    SettingsButton::Level => {
+                        let names = level_names();
+                        let next = names.iter()
+                            .position(|name| *name == settings.level())
+                            .map_or(0, |i| i + 1);
+                        if let Some(name) = names.get(next).or_else(|| names.first()) {
+                            settings.level = name.clone();
+                            settings.overrides.level = None;
+                            current_level.name = name.clone();
+                        }
+                    }
+                    // Fixes the seed of the last run, so its luck can be had again
+                    SettingsButton::Seed => {
+                        settings.seed = match settings.seed() {
+                            Some(_) => None,
+                            None => Some(rng.seed),
+                        };
+                        settings.overrides.seed = None;
+                    }
+                    SettingsButton::Back => state.set(AppState::MainMenu).unwrap(),
+                };
+                let resizes = matches!(button,
+                    SettingsButton::Fullscreen | SettingsButton::Resolution | SettingsButton::Vsync);
+                if let Some(window) = windows.get_primary_mut().filter(|_| resizes) {
+                    let (width, height) = settings.resolution();
+                    window.set_mode(settings.window_mode());
+                    window.set_resolution(width as f32, height as f32);
+                    window.set_vsync(settings.vsync());
+                }
+            }
+            Interaction::Hovered => *color = HOVERED_BUTTON.into(),
+            Interaction::None => *color = NORMAL_BUTTON.into(),
+        }
+    }
+}
+
+pub fn update_labels(
+    settings: Res<Settings>,
+    button_query: Query<(&SettingsButton, &Children)>,
+    mut text_query: Query<&mut Text>,
+) {
+    if !settings.is_changed() {
+        return;
+    }
+    for (button, children) in button_query.iter() {
+        for &child in children.iter() {
+            if let Ok(mut text) = text_query.get_mut(child) {
+                text.sections[0].value = button.label(&settings);
+            }
+        }
+    }
+}
+
+pub fn save(settings: Res<Settings>) {
+    settings.save();
+}
+
+pub fn setup(mut commands: Commands, assets: Res<GameAssets>, settings: Res<Settings>) {
+    let font = assets.font.clone();
+    let text_style = TextStyle {
+        font,
+        font_size: 24.0,
+        color: Color::rgb(0.9, 0.9, 0.9),
+    };
+
+    commands
+        .spawn_bundle(NodeBundle {
+            style: Style {
+                size: Size::new(Val::Percent(100.0), Val::Percent(100.0)),
+                // list the settings from the top
+                flex_direction: FlexDirection::ColumnReverse,
+                justify_content: JustifyContent::Center,
+                align_items: AlignItems::Center,
+                ..Default::default()
+            },
+            color: Color::NONE.into(),
+            ..Default::default()
+        })
+        .with_children(|parent| {
+            for button in SettingsButton::OPTIONS {
+                parent
+                    .spawn_bundle(NodeBundle {
+                        style: Style {
+                            align_items: AlignItems::Center,
+                            ..Default::default()
+                        },
+                        color: Color::NONE.into(),
+                        ..Default::default()
+                    })
+                    .with_children(|row| {
+                        // Setting name
+                        row.spawn_bundle(TextBundle {
+                            style: Style {
+                                size: Size::new(Val::Px(250.0), Val::Auto),
+                                ..Default::default()
+                            },
+                            text: Text::with_section(
+                                button.name(), text_style.clone(), Default::default()),
+                            ..Default::default()
+                        });
+                        spawn_button(row, &button.label(&settings), list_button(), &text_style, button);
+                    });
+            }
+            spawn_button(parent, SettingsButton::Back.name(), list_button(), &text_style, SettingsButton::Back);
+        });
+}
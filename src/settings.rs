@@ -0,0 +1,206 @@
+use bevy::prelude::*;
+use bevy::window::WindowMode;
+use serde::{Serialize, Deserialize};
+
+use crate::config;
+
+const SETTINGS_FILE: &str = "settings.ron";
+// The window sizes the settings screen cycles through
+pub const RESOLUTIONS: [(u32, u32); 4] = [(1280, 720), (1600, 900), (1920, 1080), (2560, 1440)];
+// The tile sizes, in screen pixels at the default zoom, the settings screen cycles through
+pub const TILE_SIZES: [u32; 3] = [32, 64, 128];
+
+// The settings given on the command line, which win over the
+// file for this run but are not saved
+#[derive(Clone, Default)]
+pub struct Overrides {
+    pub fullscreen: Option<bool>,
+    pub resolution: Option<(u32, u32)>,
+    pub vsync: Option<bool>,
+    pub tile_size: Option<u32>,
+    pub level: Option<String>,
+    pub seed: Option<u64>,
+}
+
+// Persisted in config/settings.ron, and loaded before the app is built
+// so that the window opens as set
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub fullscreen: bool,
+    // The size of the window when not fullscreen
+    pub resolution: (u32, u32),
+    pub vsync: bool,
+    // The size of a tile in pixels, which the art is scaled to
+    pub tile_size: u32,
+    // The level played first
+    pub level: String,
+    // Measurements are seeded with this every run if set,
+    // and with a random seed otherwise
+    pub seed: Option<u64>,
+    #[serde(skip)]
+    pub overrides: Overrides,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings{
+            fullscreen: true,
+            resolution: (1280, 720),
+            vsync: true,
+            tile_size: 64,
+            level: "first".to_string(),
+            seed: None,
+            overrides: Overrides::default(),
+        }
+    }
+}
+
+impl Settings {
+    pub fn load() -> Self {
+        config::load(SETTINGS_FILE).unwrap_or_default()
+    }
+
+    pub fn save(&self) {
+        config::save(SETTINGS_FILE, self);
+    }
+
+    pub fn apply_args(&mut self, args: &[String]) -> Result<(), String> {
+        /*
+         * Reads the overrides `--windowed`, `--resolution <width>x<height>`,
+         * `--vsync <on|off>`, `--tile-size <pixels>`, `--level <name>` and `--seed <n>`
+         */
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = |what: &str| args.next()
+                .ok_or_else(|| format!("{} needs {}", arg, what));
+            match arg.as_str() {
+                "--windowed" => self.overrides.fullscreen = Some(false),
+                "--resolution" => {
+                    let text = value("a size like 1280x720")?;
+                    let size = text.split_once('x')
+                        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)))
+                        .ok_or_else(|| format!("Not a size like 1280x720: {}", text))?;
+                    self.overrides.resolution = Some(size);
+                }
+                "--vsync" => self.overrides.vsync = Some(match value("on or off")?.as_str() {
+                    "on" => true,
+                    "off" => false,
+                    other => return Err(format!("--vsync is on or off, not {}", other)),
+                }),
+                "--tile-size" => {
+                    let text = value("a size in pixels")?;
+                    self.overrides.tile_size = Some(text.parse().ok()
+                        .filter(|size| *size > 0)
+                        .ok_or_else(|| format!("Not a tile size: {}", text))?);
+                }
+                "--level" => self.overrides.level = Some(value("a level name")?.clone()),
+                "--seed" => {
+                    let text = value("a number")?;
+                    self.overrides.seed = Some(text.parse()
+                        .map_err(|_| format!("Not a seed: {}", text))?);
+                }
+                other => return Err(format!("Unknown argument {}", other)),
+            }
+        }
+        Ok(())
+    }
+
+    // The settings in effect, with the overrides
+    pub fn fullscreen(&self) -> bool {
+        self.overrides.fullscreen.unwrap_or(self.fullscreen)
+    }
+
+    pub fn resolution(&self) -> (u32, u32) {
+        self.overrides.resolution.unwrap_or(self.resolution)
+    }
+
+    pub fn vsync(&self) -> bool {
+        self.overrides.vsync.unwrap_or(self.vsync)
+    }
+
+    pub fn tile_size(&self) -> u32 {
+        // A tile can not be smaller than a pixel, whatever the file says
+        self.overrides.tile_size.unwrap_or(self.tile_size).max(1)
+    }
+
+    pub fn level(&self) -> String {
+        self.overrides.level.clone().unwrap_or_else(|| self.level.clone())
+    }
+
+    pub fn seed(&self) -> Option<u64> {
+        self.overrides.seed.or(self.seed)
+    }
+
+    pub fn window(&self) -> WindowDescriptor {
+        let (width, height) = self.resolution();
+        WindowDescriptor {
+            title: "Quantum Adventure".to_string(),
+            mode: self.window_mode(),
+            width: width as f32,
+            height: height as f32,
+            vsync: self.vsync(),
+            ..Default::default()
+        }
+    }
+
+    pub fn window_mode(&self) -> WindowMode {
+        if self.fullscreen() {
+            WindowMode::BorderlessFullscreen
+        } else {
+            WindowMode::Windowed
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &[&str]) -> Result<Settings, String> {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        let mut settings = Settings::default();
+        settings.apply_args(&args).map(|_| settings)
+    }
+
+    #[test]
+    fn overrides_win_over_the_file() {
+        let settings = parse(&["--windowed", "--resolution", "1600x900", "--vsync", "off",
+                               "--tile-size", "32", "--level", "second", "--seed", "42"]).unwrap();
+        assert!(!settings.fullscreen());
+        assert_eq!(settings.resolution(), (1600, 900));
+        assert!(!settings.vsync());
+        assert_eq!(settings.tile_size(), 32);
+        assert_eq!(settings.level(), "second");
+        assert_eq!(settings.seed(), Some(42));
+        // The file's settings are kept, to be saved as they were
+        assert!(settings.fullscreen);
+        assert_eq!(settings.tile_size, 64);
+    }
+
+    #[test]
+    fn no_overrides() {
+        let settings = parse(&[]).unwrap();
+        assert_eq!(settings.resolution(), (1280, 720));
+        assert_eq!(settings.seed(), None);
+    }
+
+    #[test]
+    fn reject_malformed_values() {
+        let malformed: [&[&str]; 10] = [
+            &["--resolution", "1600"],
+            &["--resolution", "1600x"],
+            &["--resolution", "widexhigh"],
+            &["--vsync", "maybe"],
+            &["--tile-size", "0"],
+            &["--tile-size", "-32"],
+            &["--seed", "lucky"],
+            &["--seed"],
+            &["--level"],
+            &["--fullscreen"],
+        ];
+        for args in malformed {
+            assert!(parse(args).is_err(), "{:?} was accepted", args);
+        }
+    }
+}